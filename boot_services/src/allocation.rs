@@ -1,17 +1,78 @@
-use core::ops::{BitOr, BitOrAssign};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    iter::FusedIterator,
+    mem,
+    ops::{BitOr, BitOrAssign},
+    ptr,
+};
 
 use r_efi::efi;
 
 use crate::{boxed::BootServicesBox, BootServices};
 
+/// Size of a page as used by the memory services.
+pub const UEFI_PAGE_SIZE: usize = 0x1000;
+
 #[derive(Debug)]
 pub enum AllocType {
     AnyPage,
-    MaxAddress(usize),
-    Address(usize),
+    MaxAddress(PhysicalAddress),
+    Address(PhysicalAddress),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Physical address as used by the memory services.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PhysicalAddress(u64);
+
+impl PhysicalAddress {
+    /// Create a physical address from its raw value.
+    pub const fn new(address: u64) -> Self {
+        Self(address)
+    }
+
+    /// Return the raw value of the address.
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Return the address as a pointer, memory being identity mapped during boot services.
+    pub const fn as_ptr<T>(self) -> *mut T {
+        self.0 as usize as *mut T
+    }
+
+    /// Return true if the address is aligned on [`UEFI_PAGE_SIZE`].
+    pub const fn is_page_aligned(self) -> bool {
+        self.0 % UEFI_PAGE_SIZE as u64 == 0
+    }
+}
+
+impl From<u64> for PhysicalAddress {
+    fn from(address: u64) -> Self {
+        Self(address)
+    }
+}
+
+impl From<PhysicalAddress> for u64 {
+    fn from(address: PhysicalAddress) -> Self {
+        address.0
+    }
+}
+
+impl fmt::Display for PhysicalAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl fmt::Debug for PhysicalAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PhysicalAddress({:#x})", self.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct MemoryType(u32);
 
@@ -32,6 +93,97 @@ impl MemoryType {
     pub const PAL_CODE: MemoryType = MemoryType(efi::PAL_CODE);
     pub const PERSISTENT_MEMORY: MemoryType = MemoryType(efi::PERSISTENT_MEMORY);
     pub const UNACCEPTED_MEMORY_TYPE: MemoryType = MemoryType(efi::UNACCEPTED_MEMORY_TYPE);
+
+    const NAMES: [&'static str; 16] = [
+        "ReservedMemoryType",
+        "LoaderCode",
+        "LoaderData",
+        "BootServicesCode",
+        "BootServicesData",
+        "RuntimeServicesCode",
+        "RuntimeServicesData",
+        "ConventionalMemory",
+        "UnusableMemory",
+        "ACPIReclaimMemory",
+        "ACPIMemoryNVS",
+        "MemoryMappedIO",
+        "MemoryMappedIOPortSpace",
+        "PalCode",
+        "PersistentMemory",
+        "UnacceptedMemoryType",
+    ];
+
+    /// Return the name of the memory type as defined in the UEFI specification, None for OEM, OS and unknown types.
+    pub const fn name(self) -> Option<&'static str> {
+        if (self.0 as usize) < Self::NAMES.len() {
+            Some(Self::NAMES[self.0 as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Return true if the memory type is in the range reserved for OEM use (0x70000000..=0x7FFFFFFF).
+    pub const fn is_oem(self) -> bool {
+        self.0 >= 0x70000000 && self.0 <= 0x7FFFFFFF
+    }
+
+    /// Return true if the memory type is in the range reserved for OS loaders use (0x80000000..=0xFFFFFFFF).
+    pub const fn is_os(self) -> bool {
+        self.0 >= 0x80000000
+    }
+
+    /// Write `prefix(value)` honoring the width, fill and alignment of *f* like [`fmt::Formatter::pad`] does.
+    fn pad_unnamed(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        let hex_digits = (u32::BITS - self.0.leading_zeros()).div_ceil(4).max(1) as usize;
+        let len = prefix.len() + "(0x)".len() + hex_digits;
+        let padding = f.width().unwrap_or(0).saturating_sub(len);
+        let (before, after) = match f.align() {
+            Some(fmt::Alignment::Right) => (padding, 0),
+            Some(fmt::Alignment::Center) => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+        let fill = f.fill();
+        for _ in 0..before {
+            f.write_char(fill)?;
+        }
+        write!(f, "{prefix}({:#x})", self.0)?;
+        for _ in 0..after {
+            f.write_char(fill)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<u32> for MemoryType {
+    type Error = efi::Status;
+
+    /// Return [`efi::Status::INVALID_PARAMETER`] if the value is not a memory type defined by the UEFI specification
+    /// or in the OEM or OS reserved ranges.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let memory_type = MemoryType(value);
+        if memory_type.name().is_some() || memory_type.is_oem() || memory_type.is_os() {
+            Ok(memory_type)
+        } else {
+            Err(efi::Status::INVALID_PARAMETER)
+        }
+    }
+}
+
+impl fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.pad(name),
+            None if self.is_oem() => self.pad_unnamed(f, "OEM"),
+            None if self.is_os() => self.pad_unnamed(f, "OS"),
+            None => self.pad_unnamed(f, "Unknown"),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemoryType({self})")
+    }
 }
 
 impl Into<u32> for MemoryType {
@@ -40,23 +192,336 @@ impl Into<u32> for MemoryType {
     }
 }
 
-#[derive(Debug)]
+/// Memory map returned by [`BootServices::get_memory_map`].
+///
+/// The firmware may use a descriptor size bigger than [`efi::MemoryDescriptor`], so the descriptors are read from the
+/// raw buffer using *descriptor_size* as stride.
 pub struct MemoryMap<'a, B: BootServices> {
-    pub descriptors: BootServicesBox<'a, [MemoryDescriptor], B>,
+    buffer: BootServicesBox<'a, [u8], B>,
+    map_size: usize,
     pub map_key: usize,
+    descriptor_size: usize,
     pub descriptor_version: u32,
 }
 
+impl<'a, B: BootServices> MemoryMap<'a, B> {
+    /// Create a memory map from the buffer filled by `GetMemoryMap()`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if *map_size* is bigger than the buffer or if *descriptor_size* is smaller than
+    /// [`efi::MemoryDescriptor`].
+    pub fn new(
+        buffer: BootServicesBox<'a, [u8], B>,
+        map_size: usize,
+        map_key: usize,
+        descriptor_size: usize,
+        descriptor_version: u32,
+    ) -> Self {
+        assert!(map_size <= buffer.len(), "memory map size bigger than its buffer.");
+        assert!(descriptor_size >= mem::size_of::<efi::MemoryDescriptor>(), "memory descriptor size too small.");
+        Self { buffer, map_size, map_key, descriptor_size, descriptor_version }
+    }
+
+    /// Return the buffer, map size, map key, descriptor size and descriptor version.
+    pub(crate) fn into_raw_parts(self) -> (BootServicesBox<'a, [u8], B>, usize, usize, usize, u32) {
+        (self.buffer, self.map_size, self.map_key, self.descriptor_size, self.descriptor_version)
+    }
+
+    /// Size in bytes of the memory map in the buffer.
+    pub fn map_size(&self) -> usize {
+        self.map_size
+    }
+
+    /// Size in bytes of a descriptor, which is the stride between two descriptors in the buffer.
+    pub fn descriptor_size(&self) -> usize {
+        self.descriptor_size
+    }
+
+    /// Raw memory map as returned by the firmware.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.map_size]
+    }
+
+    /// Number of descriptors in the memory map.
+    pub fn len(&self) -> usize {
+        self.map_size / self.descriptor_size
+    }
+
+    /// Return true if the memory map does not contain any descriptor.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the descriptor at *index* or None if out of bound.
+    pub fn get(&self, index: usize) -> Option<MemoryDescriptor> {
+        if index >= self.len() {
+            return None;
+        }
+        let offset = index * self.descriptor_size;
+        let bytes = &self.buffer[offset..offset + mem::size_of::<efi::MemoryDescriptor>()];
+        //SAFETY: The slice is big enough for a descriptor, and the firmware does not guarantee its alignment.
+        let descriptor = unsafe { ptr::read_unaligned(bytes.as_ptr() as *const efi::MemoryDescriptor) };
+        Some(descriptor.into())
+    }
+
+    /// Return an iterator over the descriptors of the memory map.
+    pub fn iter(&self) -> MemoryDescriptorIter<'_, 'a, B> {
+        MemoryDescriptorIter { memory_map: self, index: 0 }
+    }
+
+    /// Return the descriptors sorted by physical address.
+    pub fn sorted(&self) -> Vec<MemoryDescriptor> {
+        let mut descriptors = self.iter().collect::<Vec<_>>();
+        descriptors.sort_by_key(|d| d.physical_start);
+        descriptors
+    }
+
+    /// Return the descriptors sorted by physical address, with the adjacent ranges of identical type and attributes
+    /// merged together.
+    pub fn coalesced(&self) -> Vec<MemoryDescriptor> {
+        coalesce(self.sorted())
+    }
+
+    /// Return the total number of pages of each memory type.
+    pub fn pages_per_type(&self) -> BTreeMap<MemoryType, u64> {
+        let mut pages = BTreeMap::new();
+        for descriptor in self {
+            *pages.entry(descriptor.memory_type).or_insert(0) += descriptor.nb_pages;
+        }
+        pages
+    }
+
+    /// Return the total number of pages of *memory_type*.
+    pub fn pages_of_type(&self, memory_type: MemoryType) -> u64 {
+        self.iter().filter(|d| d.memory_type == memory_type).map(|d| d.nb_pages).sum()
+    }
+
+    /// Return the largest range of *memory_type*, after merging adjacent ranges.
+    pub fn largest_of_type(&self, memory_type: MemoryType) -> Option<MemoryDescriptor> {
+        self.coalesced().into_iter().filter(|d| d.memory_type == memory_type).max_by_key(|d| d.nb_pages)
+    }
+
+    /// Return an iterator over the descriptors covering *address*.
+    ///
+    /// A valid memory map has at most one descriptor covering an address.
+    pub fn find_covering(&self, address: u64) -> impl Iterator<Item = MemoryDescriptor> + '_ {
+        self.iter().filter(move |d| d.contains(address))
+    }
+
+    /// Return a structure that displays the memory map as a table, like the `memmap` shell command.
+    pub fn table(&self) -> MemoryMapTable<'_, 'a, B> {
+        MemoryMapTable(self)
+    }
+
+    /// Return the ranges that differ between self and *other*, self being the old map.
+    ///
+    /// Both maps are coalesced before being compared, so splitting a range without changing it is not a difference.
+    pub fn diff<B2: BootServices>(&self, other: &MemoryMap<'_, B2>) -> MemoryMapDiff {
+        MemoryMapDiff::new(self.coalesced(), other.coalesced())
+    }
+}
+
+impl<'m, 'a, B: BootServices> IntoIterator for &'m MemoryMap<'a, B> {
+    type Item = MemoryDescriptor;
+    type IntoIter = MemoryDescriptorIter<'m, 'a, B>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<B: BootServices> fmt::Debug for MemoryMap<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryMap")
+            .field("map_key", &self.map_key)
+            .field("descriptor_size", &self.descriptor_size)
+            .field("descriptor_version", &self.descriptor_version)
+            .field("descriptors", &DebugDescriptors(self))
+            .finish()
+    }
+}
+
+struct DebugDescriptors<'m, 'a, B: BootServices>(&'m MemoryMap<'a, B>);
+
+impl<B: BootServices> fmt::Debug for DebugDescriptors<'_, '_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter()).finish()
+    }
+}
+
+/// Table view of a [`MemoryMap`], see [`MemoryMap::table`].
+///
+/// ```text
+/// Type                    Start            End              # Pages          Attributes
+/// ConventionalMemory      0000000000001000-0000000000010FFF 0000000000000010 WB
+/// BootServicesData        0000000000011000-0000000000012FFF 0000000000000002 WB|XP
+///
+///   BootServicesData      :          2 Pages (8192 Bytes)
+///   ConventionalMemory    :         16 Pages (65536 Bytes)
+/// Total Memory            :         18 Pages (73728 Bytes)
+/// ```
+pub struct MemoryMapTable<'m, 'a, B: BootServices>(&'m MemoryMap<'a, B>);
+
+impl<B: BootServices> fmt::Display for MemoryMapTable<'_, '_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<23} {:<16} {:<16} {:<16} Attributes", "Type", "Start", "End", "# Pages")?;
+        for descriptor in self.0 {
+            writeln!(
+                f,
+                "{:<23} {:016X}-{:016X} {:016X} {}",
+                descriptor.memory_type,
+                descriptor.physical_start,
+                descriptor.end().saturating_sub(1),
+                descriptor.nb_pages,
+                descriptor.attribute
+            )?;
+        }
+        writeln!(f)?;
+        let mut total_pages = 0_u64;
+        for (memory_type, pages) in self.0.pages_per_type() {
+            total_pages += pages;
+            writeln!(f, "  {memory_type:<22}: {pages:>10} Pages ({} Bytes)", pages * UEFI_PAGE_SIZE as u64)?;
+        }
+        writeln!(f, "{:<24}: {total_pages:>10} Pages ({} Bytes)", "Total Memory", total_pages * UEFI_PAGE_SIZE as u64)
+    }
+}
+
+impl<B: BootServices> fmt::Debug for MemoryMapTable<'_, '_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Iterator over the descriptors of a [`MemoryMap`].
 #[derive(Debug)]
+pub struct MemoryDescriptorIter<'m, 'a, B: BootServices> {
+    memory_map: &'m MemoryMap<'a, B>,
+    index: usize,
+}
+
+impl<B: BootServices> Iterator for MemoryDescriptorIter<'_, '_, B> {
+    type Item = MemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let descriptor = self.memory_map.get(self.index)?;
+        self.index += 1;
+        Some(descriptor)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.memory_map.len().saturating_sub(self.index);
+        (len, Some(len))
+    }
+}
+
+impl<B: BootServices> ExactSizeIterator for MemoryDescriptorIter<'_, '_, B> {}
+
+impl<B: BootServices> FusedIterator for MemoryDescriptorIter<'_, '_, B> {}
+
+/// Descriptor of a memory region, with the same layout as [`efi::MemoryDescriptor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
 pub struct MemoryDescriptor {
     pub memory_type: MemoryType,
-    pub physical_start: usize,
-    pub virtual_start: usize,
-    pub nb_pages: usize,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub nb_pages: u64,
     pub attribute: MemoryAttribute,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl MemoryDescriptor {
+    /// Size in bytes of the range.
+    pub const fn size(&self) -> u64 {
+        self.nb_pages.saturating_mul(UEFI_PAGE_SIZE as u64)
+    }
+
+    /// Physical address right after the end of the range.
+    pub const fn end(&self) -> u64 {
+        self.physical_start.saturating_add(self.size())
+    }
+
+    /// Return true if *address* is in the range.
+    pub const fn contains(&self, address: u64) -> bool {
+        self.physical_start <= address && address < self.end()
+    }
+
+    /// Return true if *other* starts right after self and can be merged with it.
+    fn can_merge(&self, other: &MemoryDescriptor) -> bool {
+        let virtual_contiguous = (self.virtual_start == 0 && other.virtual_start == 0)
+            || self.virtual_start.saturating_add(self.size()) == other.virtual_start;
+        self.memory_type == other.memory_type
+            && self.attribute == other.attribute
+            && self.end() == other.physical_start
+            && virtual_contiguous
+    }
+}
+
+/// Merge the adjacent ranges of identical type and attributes, *descriptors* must be sorted by physical address.
+fn coalesce(descriptors: Vec<MemoryDescriptor>) -> Vec<MemoryDescriptor> {
+    let mut coalesced: Vec<MemoryDescriptor> = Vec::with_capacity(descriptors.len());
+    for descriptor in descriptors {
+        match coalesced.last_mut() {
+            Some(last) if last.can_merge(&descriptor) => last.nb_pages += descriptor.nb_pages,
+            _ => coalesced.push(descriptor),
+        }
+    }
+    coalesced
+}
+
+/// Differences between two memory maps, see [`MemoryMap::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMapDiff {
+    /// Ranges that are only in the new map.
+    pub added: Vec<MemoryDescriptor>,
+    /// Ranges that are only in the old map.
+    pub removed: Vec<MemoryDescriptor>,
+}
+
+impl MemoryMapDiff {
+    fn new(old: Vec<MemoryDescriptor>, new: Vec<MemoryDescriptor>) -> Self {
+        let added = new.iter().filter(|d| !old.contains(d)).copied().collect();
+        let removed = old.iter().filter(|d| !new.contains(d)).copied().collect();
+        Self { added, removed }
+    }
+
+    /// Return true if both maps are identical.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+const _: () = {
+    assert!(mem::size_of::<MemoryDescriptor>() == mem::size_of::<efi::MemoryDescriptor>());
+    assert!(mem::align_of::<MemoryDescriptor>() == mem::align_of::<efi::MemoryDescriptor>());
+};
+
+impl From<efi::MemoryDescriptor> for MemoryDescriptor {
+    fn from(descriptor: efi::MemoryDescriptor) -> Self {
+        Self {
+            memory_type: MemoryType(descriptor.r#type),
+            physical_start: descriptor.physical_start,
+            virtual_start: descriptor.virtual_start,
+            nb_pages: descriptor.number_of_pages,
+            attribute: MemoryAttribute(descriptor.attribute),
+        }
+    }
+}
+
+impl From<MemoryDescriptor> for efi::MemoryDescriptor {
+    fn from(descriptor: MemoryDescriptor) -> Self {
+        Self {
+            r#type: descriptor.memory_type.into(),
+            physical_start: descriptor.physical_start,
+            virtual_start: descriptor.virtual_start,
+            number_of_pages: descriptor.nb_pages,
+            attribute: descriptor.attribute.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
 pub struct MemoryAttribute(u64);
 
 impl MemoryAttribute {
@@ -76,6 +541,49 @@ impl MemoryAttribute {
     pub const RUNTIME: MemoryAttribute = MemoryAttribute(efi::MEMORY_RUNTIME);
     pub const ISA_VALID: MemoryAttribute = MemoryAttribute(efi::MEMORY_ISA_VALID);
     pub const ISA_MASK: MemoryAttribute = MemoryAttribute(efi::MEMORY_ISA_MASK);
+
+    const NAMES: [(MemoryAttribute, &'static str); 15] = [
+        (Self::UC, "UC"),
+        (Self::WC, "WC"),
+        (Self::WT, "WT"),
+        (Self::WB, "WB"),
+        (Self::UCE, "UCE"),
+        (Self::WP, "WP"),
+        (Self::RP, "RP"),
+        (Self::XP, "XP"),
+        (Self::NV, "NV"),
+        (Self::MORE_RELIABLE, "MORE_RELIABLE"),
+        (Self::RO, "RO"),
+        (Self::SP, "SP"),
+        (Self::CPU_CRYPTO, "CPU_CRYPTO"),
+        (Self::RUNTIME, "RUNTIME"),
+        (Self::ISA_VALID, "ISA_VALID"),
+    ];
+}
+
+impl fmt::Display for MemoryAttribute {
+    /// Display the attributes separated by `|`, e.g. `WB|XP|RUNTIME`, bits without name are displayed in hexadecimal.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut remaining = self.0;
+        let mut separator = "";
+        for (attribute, name) in Self::NAMES {
+            if remaining & attribute.0 != 0 {
+                write!(f, "{separator}{name}")?;
+                remaining &= !attribute.0;
+                separator = "|";
+            }
+        }
+        if remaining != 0 || self.0 == 0 {
+            write!(f, "{separator}{remaining:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MemoryAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MemoryAttribute({self})")
+    }
 }
 
 impl BitOr for MemoryAttribute {
@@ -107,3 +615,214 @@ impl Into<u64> for MemoryAttribute {
         self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use alloc::{boxed::Box, vec};
+
+    fn descriptor(memory_type: MemoryType, physical_start: u64, nb_pages: u64) -> MemoryDescriptor {
+        MemoryDescriptor { memory_type, physical_start, virtual_start: 0, nb_pages, attribute: MemoryAttribute::WB }
+    }
+
+    fn memory_map<'a>(
+        boot_services: &'a MockBootServices,
+        descriptors: &[MemoryDescriptor],
+    ) -> MemoryMap<'a, MockBootServices> {
+        let descriptor_size = mem::size_of::<efi::MemoryDescriptor>() + 8;
+        let mut buffer = vec![0_u8; descriptors.len() * descriptor_size].into_boxed_slice();
+        for (i, descriptor) in descriptors.iter().enumerate() {
+            let descriptor = efi::MemoryDescriptor::from(*descriptor);
+            unsafe { ptr::write_unaligned(buffer.as_mut_ptr().add(i * descriptor_size) as *mut _, descriptor) };
+        }
+        let len = buffer.len();
+        let buffer = unsafe { BootServicesBox::from_raw_parts(Box::leak(buffer).as_mut_ptr(), len, boot_services) };
+        MemoryMap::new(buffer, len, 1, descriptor_size, efi::MEMORY_DESCRIPTOR_VERSION)
+    }
+
+    fn boot_services() -> MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_free_pool().returning(|_| Ok(()));
+        boot_services
+    }
+
+    #[test]
+    fn test_memory_descriptor_range() {
+        let d = descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 2);
+        assert_eq!(0x2000, d.size());
+        assert_eq!(0x3000, d.end());
+        assert!(!d.contains(0xFFF));
+        assert!(d.contains(0x1000));
+        assert!(d.contains(0x2FFF));
+        assert!(!d.contains(0x3000));
+    }
+
+    #[test]
+    fn test_memory_map_sorted_and_coalesced() {
+        let boot_services = boot_services();
+        let memory_map = memory_map(
+            &boot_services,
+            &[
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x3000, 1),
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 2),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x4000, 1),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x5000, 1),
+                // Not adjacent.
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x7000, 1),
+                MemoryDescriptor {
+                    attribute: MemoryAttribute::WB | MemoryAttribute::XP,
+                    ..descriptor(MemoryType::BOOT_SERVICES_DATA, 0x8000, 1)
+                },
+            ],
+        );
+
+        let sorted = memory_map.sorted();
+        assert!(sorted.windows(2).all(|w| w[0].physical_start < w[1].physical_start));
+
+        assert_eq!(
+            vec![
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 3),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x4000, 2),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x7000, 1),
+                MemoryDescriptor {
+                    attribute: MemoryAttribute::WB | MemoryAttribute::XP,
+                    ..descriptor(MemoryType::BOOT_SERVICES_DATA, 0x8000, 1)
+                },
+            ],
+            memory_map.coalesced()
+        );
+    }
+
+    #[test]
+    fn test_memory_map_pages_and_find() {
+        let boot_services = boot_services();
+        let memory_map = memory_map(
+            &boot_services,
+            &[
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 2),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x3000, 1),
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x4000, 3),
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x7000, 2),
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x10000, 4),
+            ],
+        );
+
+        let pages = memory_map.pages_per_type();
+        assert_eq!(2, pages.len());
+        assert_eq!(Some(&11), pages.get(&MemoryType::CONVENTIONAL_MEMORY));
+        assert_eq!(Some(&1), pages.get(&MemoryType::BOOT_SERVICES_DATA));
+        assert_eq!(11, memory_map.pages_of_type(MemoryType::CONVENTIONAL_MEMORY));
+        assert_eq!(0, memory_map.pages_of_type(MemoryType::ACPI_MEMORY_NVS));
+
+        assert_eq!(
+            Some(descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x4000, 5)),
+            memory_map.largest_of_type(MemoryType::CONVENTIONAL_MEMORY)
+        );
+        assert_eq!(None, memory_map.largest_of_type(MemoryType::ACPI_MEMORY_NVS));
+
+        assert_eq!(
+            vec![descriptor(MemoryType::BOOT_SERVICES_DATA, 0x3000, 1)],
+            memory_map.find_covering(0x3ABC).collect::<Vec<_>>()
+        );
+        assert_eq!(0, memory_map.find_covering(0x9000).count());
+    }
+
+    #[test]
+    fn test_memory_map_diff() {
+        let boot_services = boot_services();
+        let before = memory_map(
+            &boot_services,
+            &[
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 4),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x5000, 1),
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x6000, 1),
+            ],
+        );
+        let after = memory_map(
+            &boot_services,
+            &[
+                descriptor(MemoryType::BOOT_SERVICES_DATA, 0x5000, 2),
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 2),
+                descriptor(MemoryType::LOADER_DATA, 0x3000, 2),
+            ],
+        );
+
+        assert!(before.diff(&before).is_empty());
+        let diff = before.diff(&after);
+        assert_eq!(
+            vec![
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 2),
+                descriptor(MemoryType::LOADER_DATA, 0x3000, 2)
+            ],
+            diff.added
+        );
+        assert_eq!(vec![descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 4)], diff.removed);
+    }
+
+    #[test]
+    fn test_memory_type_display() {
+        assert_eq!("BootServicesData", format!("{}", MemoryType::BOOT_SERVICES_DATA));
+        assert_eq!("MemoryType(ReservedMemoryType)", format!("{:?}", MemoryType::RESERVED_MEMORY_TYPE));
+        assert_eq!("MemoryType(OEM(0x70000001))", format!("{:?}", MemoryType(0x70000001)));
+        assert_eq!("UnacceptedMemoryType", format!("{}", MemoryType::UNACCEPTED_MEMORY_TYPE));
+        assert_eq!("OEM(0x70000001)", format!("{}", MemoryType(0x70000001)));
+        assert_eq!("OS(0x80000000)", format!("{}", MemoryType(0x80000000)));
+        assert_eq!("Unknown(0x10)", format!("{}", MemoryType(0x10)));
+        assert_eq!("LoaderCode  |", format!("{:<12}|", MemoryType::LOADER_CODE));
+        assert_eq!("OEM(0x70000001)  |", format!("{:<17}|", MemoryType(0x70000001)));
+        assert_eq!("  Unknown(0x10)|", format!("{:>15}|", MemoryType(0x10)));
+        assert_eq!("*OS(0x80000000)**|", format!("{:*^17}|", MemoryType(0x80000000)));
+    }
+
+    #[test]
+    fn test_memory_type_try_from() {
+        assert_eq!(Ok(MemoryType::CONVENTIONAL_MEMORY), MemoryType::try_from(efi::CONVENTIONAL_MEMORY));
+        assert_eq!(Ok(MemoryType::UNACCEPTED_MEMORY_TYPE), MemoryType::try_from(efi::UNACCEPTED_MEMORY_TYPE));
+        assert!(MemoryType::try_from(0x70000000).unwrap().is_oem());
+        assert!(MemoryType::try_from(0x7FFFFFFF).unwrap().is_oem());
+        assert!(MemoryType::try_from(0x80000000).unwrap().is_os());
+        assert!(MemoryType::try_from(0xFFFFFFFF).unwrap().is_os());
+        assert!(!MemoryType::BOOT_SERVICES_CODE.is_oem());
+        assert!(!MemoryType::BOOT_SERVICES_CODE.is_os());
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), MemoryType::try_from(0x10));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), MemoryType::try_from(0x6FFFFFFF));
+    }
+
+    #[test]
+    fn test_memory_attribute_display() {
+        assert_eq!(
+            "WB|XP|RUNTIME",
+            format!("{}", MemoryAttribute::WB | MemoryAttribute::XP | MemoryAttribute::RUNTIME)
+        );
+        assert_eq!("UC|WC|WT|WB", format!("{}", MemoryAttribute(0xF)));
+        assert_eq!("0x0", format!("{}", MemoryAttribute(0)));
+        assert_eq!("WB|0x100000000000", format!("{}", MemoryAttribute(efi::MEMORY_WB | 0x100000000000)));
+        assert_eq!("MemoryAttribute(WB|RO)", format!("{:?}", MemoryAttribute::WB | MemoryAttribute::RO));
+    }
+
+    #[test]
+    fn test_memory_map_table() {
+        let boot_services = boot_services();
+        let memory_map = memory_map(
+            &boot_services,
+            &[
+                descriptor(MemoryType::CONVENTIONAL_MEMORY, 0x1000, 0x10),
+                MemoryDescriptor {
+                    attribute: MemoryAttribute::WB | MemoryAttribute::XP,
+                    ..descriptor(MemoryType::BOOT_SERVICES_DATA, 0x11000, 2)
+                },
+            ],
+        );
+        let expected = "\
+Type                    Start            End              # Pages          Attributes
+ConventionalMemory      0000000000001000-0000000000010FFF 0000000000000010 WB
+BootServicesData        0000000000011000-0000000000012FFF 0000000000000002 WB|XP
+
+  BootServicesData      :          2 Pages (8192 Bytes)
+  ConventionalMemory    :         16 Pages (65536 Bytes)
+Total Memory            :         18 Pages (73728 Bytes)
+";
+        assert_eq!(expected, format!("{}", memory_map.table()));
+    }
+}
@@ -0,0 +1,196 @@
+//! Allocator backed by boot services pool memory of a given [`MemoryType`].
+//!
+//! With the `allocator_api` (nightly) or `allocator_api2` feature, [`BootServicesAllocator`] can be used with
+//! collections like `Vec` and `Box`.
+//!
+//! ```ignore
+//! let allocator = BootServicesAllocator::new(&boot_services, MemoryType::RUNTIME_SERVICES_DATA);
+//! let mut buffer = Vec::with_capacity_in(0x100, allocator);
+//! buffer.extend_from_slice(b"runtime data");
+//! ```
+
+use core::{
+    alloc::Layout,
+    fmt::{self, Debug},
+    ptr::{self, NonNull},
+};
+
+use r_efi::efi;
+
+use crate::{allocation::MemoryType, BootServices};
+
+/// Alignment guaranteed by [`BootServices::allocate_pool`].
+const POOL_ALIGNMENT: usize = 8;
+
+/// Allocator that allocates pool memory of a given [`MemoryType`].
+///
+/// Allocations with an alignment bigger than the pool alignment are over-allocated, the original pointer being
+/// stored right after the aligned data to be freed later.
+pub struct BootServicesAllocator<'a, B: BootServices> {
+    boot_services: &'a B,
+    memory_type: MemoryType,
+}
+
+impl<'a, B: BootServices> BootServicesAllocator<'a, B> {
+    /// Create an allocator that allocates memory of type *memory_type*.
+    pub const fn new(boot_services: &'a B, memory_type: MemoryType) -> Self {
+        Self { boot_services, memory_type }
+    }
+
+    /// Type of memory allocated.
+    pub fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+
+    /// Allocate memory described by *layout*.
+    ///
+    /// Zero sized allocations return a dangling pointer aligned on the layout alignment without using boot services.
+    pub fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, efi::Status> {
+        if layout.size() == 0 {
+            // Layout alignment is never 0.
+            let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+        let ptr = if layout.align() <= POOL_ALIGNMENT {
+            self.boot_services.allocate_pool(self.memory_type, layout.size())?
+        } else {
+            let (extended_layout, tracker_offset) =
+                layout.extend(Layout::new::<*mut u8>()).map_err(|_| efi::Status::INVALID_PARAMETER)?;
+            let alloc_size = (extended_layout.align() - POOL_ALIGNMENT)
+                .checked_add(extended_layout.size())
+                .ok_or(efi::Status::OUT_OF_RESOURCES)?;
+            let original_ptr = self.boot_services.allocate_pool(self.memory_type, alloc_size)?;
+            unsafe {
+                let ptr = original_ptr.add(original_ptr.align_offset(extended_layout.align()));
+                ptr::write(ptr.add(tracker_offset) as *mut *mut u8, original_ptr);
+                ptr
+            }
+        };
+        let ptr = NonNull::new(ptr).ok_or(efi::Status::OUT_OF_RESOURCES)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    /// Free memory allocated with [`Self::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// *ptr* must have been allocated by this allocator, or another allocator of the same boot services, with the
+    /// same *layout*.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), efi::Status> {
+        if layout.size() == 0 {
+            return Ok(());
+        }
+        if layout.align() <= POOL_ALIGNMENT {
+            return self.boot_services.free_pool(ptr.as_ptr());
+        }
+        let (_, tracker_offset) =
+            layout.extend(Layout::new::<*mut u8>()).map_err(|_| efi::Status::INVALID_PARAMETER)?;
+        let original_ptr = ptr::read(ptr.as_ptr().add(tracker_offset) as *const *mut u8);
+        self.boot_services.free_pool(original_ptr)
+    }
+}
+
+impl<B: BootServices> Clone for BootServicesAllocator<'_, B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: BootServices> Copy for BootServicesAllocator<'_, B> {}
+
+impl<B: BootServices> Debug for BootServicesAllocator<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BootServicesAllocator").field("memory_type", &self.memory_type).finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+unsafe impl<B: BootServices> core::alloc::Allocator for BootServicesAllocator<'_, B> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        BootServicesAllocator::allocate(self, layout).map_err(|_| core::alloc::AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = BootServicesAllocator::deallocate(self, ptr, layout);
+    }
+}
+
+#[cfg(any(test, feature = "allocator_api2"))]
+unsafe impl<B: BootServices> allocator_api2::alloc::Allocator for BootServicesAllocator<'_, B> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+        BootServicesAllocator::allocate(self, layout).map_err(|_| allocator_api2::alloc::AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = BootServicesAllocator::deallocate(self, ptr, layout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{boxed::test::boot_services, MockBootServices};
+    use allocator_api2::{boxed::Box, vec::Vec};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_vec_in_memory_type() {
+        let allocations = Arc::new(Mutex::new(std::vec::Vec::new()));
+        let boot_services = boot_services(MemoryType::RUNTIME_SERVICES_DATA, allocations.clone());
+        let allocator = BootServicesAllocator::new(&boot_services, MemoryType::RUNTIME_SERVICES_DATA);
+        assert_eq!(MemoryType::RUNTIME_SERVICES_DATA, allocator.memory_type());
+
+        let mut buffer = Vec::new_in(allocator);
+        for i in 0..0x100 {
+            buffer.push(i as u8);
+        }
+        assert_eq!(0x100, buffer.len());
+        assert!(buffer.iter().enumerate().all(|(i, b)| i as u8 == *b));
+        assert_eq!(1, allocations.lock().unwrap().len());
+        drop(buffer);
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_over_aligned_allocation() {
+        #[repr(align(0x100))]
+        struct Aligned(u8);
+
+        let allocations = Arc::new(Mutex::new(std::vec::Vec::new()));
+        let boot_services = boot_services(MemoryType::ACPI_RECLAIM_MEMORY, allocations.clone());
+        let allocator = BootServicesAllocator::new(&boot_services, MemoryType::ACPI_RECLAIM_MEMORY);
+
+        let boxes = (0..8).map(|i| Box::new_in(Aligned(i), allocator)).collect::<std::vec::Vec<_>>();
+        for (i, b) in boxes.iter().enumerate() {
+            assert_eq!(0, &**b as *const Aligned as usize % 0x100);
+            assert_eq!(i as u8, b.0);
+        }
+        assert_eq!(8, allocations.lock().unwrap().len());
+        drop(boxes);
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_zero_sized_allocation() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pool().never();
+        boot_services.expect_free_pool().never();
+        let allocator = BootServicesAllocator::new(&boot_services, MemoryType::BOOT_SERVICES_DATA);
+
+        let layout = Layout::from_size_align(0, 0x40).unwrap();
+        let ptr = allocator.allocate(layout).unwrap();
+        assert_eq!(0, ptr.len());
+        assert_eq!(0, ptr.as_ptr() as *mut u8 as usize % 0x40);
+        assert_eq!(Ok(()), unsafe { allocator.deallocate(ptr.cast(), layout) });
+    }
+
+    #[test]
+    fn test_allocation_failure() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pool().returning(|_, _| Err(efi::Status::OUT_OF_RESOURCES));
+        let allocator = BootServicesAllocator::new(&boot_services, MemoryType::BOOT_SERVICES_DATA);
+
+        assert_eq!(Err(efi::Status::OUT_OF_RESOURCES), allocator.allocate(Layout::new::<u64>()));
+        assert!(Vec::<u8, _>::new_in(allocator).try_reserve(10).is_err());
+    }
+}
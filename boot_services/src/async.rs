@@ -0,0 +1,405 @@
+//! This module defined a minimal single threaded executor driven by events, and futures over events.
+//!
+//! ```ignore
+//! let executor = Executor::new(&boot_services);
+//! executor.block_on(async {
+//!     sleep(&boot_services, Duration::from_secs(1))?.await;
+//!     Ok(())
+//! })?
+//! ```
+
+use alloc::{boxed::Box, rc::Rc, sync::Arc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    fmt,
+    future::{self, Future},
+    mem,
+    pin::{pin, Pin},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+
+use r_efi::efi;
+
+use crate::{
+    event::{self, Event, EventTimerType, EventType},
+    tpl::Tpl,
+    BootServices,
+};
+
+/// State shared between an [`EventFuture`] and the notify function of its event.
+#[derive(Default)]
+struct EventState {
+    signaled: Cell<bool>,
+    waker: Cell<Option<Waker>>,
+}
+
+/// Future that completes when its event is signaled.
+///
+/// The future can be awaited again by reference (`(&mut future).await`) to wait for the next signal.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct EventFuture<'a, B: BootServices> {
+    boot_services: &'a B,
+    event: Event<'a, B>,
+    notify_tpl: Tpl,
+    state: Rc<EventState>,
+}
+
+impl<'a, B: BootServices> EventFuture<'a, B> {
+    /// Create an event of type *event_type* | [`EventType::NOTIFY_SIGNAL`] that wakes this future when signaled.
+    ///
+    /// The event can be used with other services, e.g. [`BootServices::register_protocol_notify`], see [`Self::event`].
+    pub fn new(boot_services: &'a B, event_type: EventType, notify_tpl: Tpl) -> Result<Self, efi::Status> {
+        let state = Rc::new(EventState::default());
+        let event = {
+            let state = state.clone();
+            Event::new_with_closure(boot_services, event_type | EventType::NOTIFY_SIGNAL, notify_tpl, move |_| {
+                state.signaled.set(true);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            })?
+        };
+        Ok(Self { boot_services, event, notify_tpl, state })
+    }
+
+    /// Return the underlying event.
+    pub fn event(&self) -> &Event<'a, B> {
+        &self.event
+    }
+}
+
+impl<B: BootServices> Future for EventFuture<'_, B> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The notify function runs at notify_tpl, raise to it so it can not run between the check and the waker update.
+        let old_tpl = self.boot_services.raise_tpl(self.notify_tpl);
+        let poll = if self.state.signaled.replace(false) {
+            self.state.waker.set(None);
+            Poll::Ready(())
+        } else {
+            self.state.waker.set(Some(cx.waker().clone()));
+            Poll::Pending
+        };
+        self.boot_services.restore_tpl(old_tpl);
+        poll
+    }
+}
+
+impl<B: BootServices> fmt::Debug for EventFuture<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventFuture").field("event", &self.event).field("notify_tpl", &self.notify_tpl).finish()
+    }
+}
+
+/// Return a future that completes once *duration* has elapsed.
+pub fn sleep<B: BootServices>(boot_services: &B, duration: Duration) -> Result<EventFuture<'_, B>, efi::Status> {
+    timer_future(boot_services, EventTimerType::Relative, duration)
+}
+
+/// Return a future that completes every *period*, it is meant to be awaited by reference in a loop.
+pub fn interval<B: BootServices>(boot_services: &B, period: Duration) -> Result<EventFuture<'_, B>, efi::Status> {
+    timer_future(boot_services, EventTimerType::Periodic, period)
+}
+
+fn timer_future<B: BootServices>(
+    boot_services: &B,
+    timer_type: EventTimerType,
+    duration: Duration,
+) -> Result<EventFuture<'_, B>, efi::Status> {
+    let future = EventFuture::new(boot_services, EventType::TIMER, Tpl::CALLBACK)?;
+    future.event.set_timer(timer_type, event::duration_to_trigger_time(duration))?;
+    Ok(future)
+}
+
+/// Data of the waker used by the [`Executor`], waking signals the event the executor is waiting on.
+struct WakeSignal<B: BootServices> {
+    boot_services: *const B,
+    event: efi::Event,
+    alive: AtomicBool,
+}
+
+impl<B: BootServices> WakeSignal<B> {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(Self::clone, Self::wake, Self::wake_by_ref, Self::drop);
+
+    fn waker(self: &Arc<Self>) -> Waker {
+        let data = Arc::into_raw(self.clone()) as *const ();
+        //SAFETY: The vtable functions respect the RawWaker contract for an Arc<Self> data pointer.
+        unsafe { Waker::from_raw(RawWaker::new(data, &Self::VTABLE)) }
+    }
+
+    unsafe fn clone(data: *const ()) -> RawWaker {
+        Arc::increment_strong_count(data as *const Self);
+        RawWaker::new(data, &Self::VTABLE)
+    }
+
+    unsafe fn wake(data: *const ()) {
+        Self::wake_by_ref(data);
+        Self::drop(data);
+    }
+
+    unsafe fn wake_by_ref(data: *const ()) {
+        let signal = &*(data as *const Self);
+        // Wakers can outlive the executor, the boot services and the event are only used while it is running.
+        if signal.alive.load(Ordering::SeqCst) {
+            let _ = (*signal.boot_services).signal_event(signal.event);
+        }
+    }
+
+    unsafe fn drop(data: *const ()) {
+        drop(Arc::from_raw(data as *const Self));
+    }
+}
+
+/// Minimal single threaded executor.
+///
+/// The executor waits on an event with [`BootServices::wait_for_event`] until a waker signals it,
+/// then polls every task again. Like [`BootServices::wait_for_event`], it must run at [`Tpl::APPLICATION`].
+pub struct Executor<'a, B: BootServices> {
+    boot_services: &'a B,
+    tasks: RefCell<Vec<Pin<Box<dyn Future<Output = ()> + 'a>>>>,
+    spawned: Cell<bool>,
+}
+
+impl<'a, B: BootServices> Executor<'a, B> {
+    /// Create an executor without any task.
+    pub fn new(boot_services: &'a B) -> Self {
+        Self { boot_services, tasks: RefCell::new(Vec::new()), spawned: Cell::new(false) }
+    }
+
+    /// Add a task to the executor, it is polled by [`Self::block_on`] and [`Self::run`].
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'a) {
+        self.tasks.borrow_mut().push(Box::pin(future));
+        self.spawned.set(true);
+    }
+
+    /// Run the executor until *future* completes and return its output.
+    ///
+    /// Spawned tasks are polled along with *future*, the ones that are not completed are kept in the executor.
+    pub fn block_on<F: Future>(&self, future: F) -> Result<F::Output, efi::Status> {
+        //SAFETY: There is no notify function and no notify context.
+        let event = unsafe {
+            self.boot_services.create_event_unchecked::<c_void>(EventType::NONE, Tpl::CALLBACK, None, ptr::null_mut())
+        }?;
+        //SAFETY: The event was just created and is not owned by anything else.
+        let event = unsafe { Event::from_raw(self.boot_services, event) };
+
+        let signal = Arc::new(WakeSignal {
+            boot_services: self.boot_services as *const B,
+            event: event.as_raw(),
+            alive: AtomicBool::new(true),
+        });
+        let waker = signal.waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        let output = loop {
+            self.poll_tasks(&mut cx);
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                break Ok(output);
+            }
+            if self.spawned.replace(false) {
+                // New tasks have not been polled yet.
+                continue;
+            }
+            if let Err(status) = self.boot_services.wait_for_event(&mut [event.as_raw()]) {
+                break Err(status);
+            }
+        };
+        signal.alive.store(false, Ordering::SeqCst);
+        output
+    }
+
+    /// Run the executor until every spawned task is completed.
+    pub fn run(&self) -> Result<(), efi::Status> {
+        self.block_on(future::poll_fn(|_| if self.tasks.borrow().is_empty() { Poll::Ready(()) } else { Poll::Pending }))
+    }
+
+    fn poll_tasks(&self, cx: &mut Context<'_>) {
+        self.spawned.set(false);
+        let mut tasks = mem::take(&mut *self.tasks.borrow_mut());
+        tasks.retain_mut(|task| task.as_mut().poll(cx).is_pending());
+        // Tasks spawned while polling are kept after the older ones.
+        let mut spawned = self.tasks.borrow_mut();
+        tasks.append(&mut spawned);
+        *spawned = tasks;
+    }
+}
+
+impl<B: BootServices> fmt::Debug for Executor<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor").field("tasks", &self.tasks.borrow().len()).finish()
+    }
+}
+
+/// Run *future* to completion on a new [`Executor`] and return its output.
+pub fn block_on<B: BootServices, F: Future>(boot_services: &B, future: F) -> Result<F::Output, efi::Status> {
+    Executor::new(boot_services).block_on(future)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use core::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    const WAKE_EVENT: usize = 1;
+    const TIMER_EVENT: usize = 2;
+
+    type NotifyFunction = extern "efiapi" fn(efi::Event, *mut c_void);
+
+    fn expect_wake_event(boot_services: &mut MockBootServices) {
+        boot_services
+            .expect_create_event_unchecked::<c_void>()
+            .withf(|event_type, _, notify_function, _| *event_type == EventType::NONE && notify_function.is_none())
+            .times(1)
+            .returning(|_, _, _, _| Ok(WAKE_EVENT as efi::Event));
+        boot_services.expect_close_event().withf(|event| *event as usize == WAKE_EVENT).times(1).returning(|_| Ok(()));
+    }
+
+    fn expect_tpl(boot_services: &mut MockBootServices) {
+        boot_services.expect_raise_tpl().return_const(Tpl::APPLICATION);
+        boot_services.expect_restore_tpl().return_const(());
+    }
+
+    /// Future that wakes itself once before completing.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_on_ready_future() {
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        boot_services.expect_wait_for_event().never();
+
+        assert_eq!(Ok(42), block_on(&boot_services, async { 42 }));
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_block_on_wait_until_woken() {
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        boot_services.expect_signal_event().withf(|event| *event as usize == WAKE_EVENT).times(1).returning(|_| Ok(()));
+        boot_services
+            .expect_wait_for_event()
+            .withf(|events| events.len() == 1 && events[0] as usize == WAKE_EVENT)
+            .times(1)
+            .returning(|_| Ok(0));
+
+        assert_eq!(Ok(()), block_on(&boot_services, YieldOnce(false)));
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_block_on_return_wait_error() {
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        boot_services.expect_wait_for_event().times(1).returning(|_| Err(efi::Status::UNSUPPORTED));
+
+        assert_eq!(Err(efi::Status::UNSUPPORTED), block_on(&boot_services, future::pending::<()>()));
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_waker_outliving_executor_does_nothing() {
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        boot_services.expect_signal_event().never();
+
+        let waker = block_on(&boot_services, future::poll_fn(|cx| Poll::Ready(cx.waker().clone()))).unwrap();
+        waker.wake();
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_spawn_and_run() {
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        boot_services.expect_signal_event().returning(|_| Ok(()));
+        boot_services.expect_wait_for_event().times(1).returning(|_| Ok(0));
+
+        let done = Rc::new(Cell::new(0));
+        let executor = Executor::new(&boot_services);
+        for _ in 0..2 {
+            let done = done.clone();
+            executor.spawn(async move {
+                YieldOnce(false).await;
+                done.set(done.get() + 1);
+            });
+        }
+        executor.run().unwrap();
+        assert_eq!(2, done.get());
+        drop(executor);
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_sleep() {
+        static NOTIFY: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+        static SIGNAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut boot_services = MockBootServices::new();
+        expect_wake_event(&mut boot_services);
+        expect_tpl(&mut boot_services);
+        boot_services
+            .expect_create_event_unchecked::<c_void>()
+            .withf(|event_type, notify_tpl, notify_function, _| {
+                *event_type == EventType::TIMER | EventType::NOTIFY_SIGNAL
+                    && *notify_tpl == Tpl::CALLBACK
+                    && notify_function.is_some()
+            })
+            .times(1)
+            .returning(|_, _, notify_function, notify_context| {
+                *NOTIFY.lock().unwrap() = Some((notify_function.unwrap() as usize, notify_context as usize));
+                Ok(TIMER_EVENT as efi::Event)
+            });
+        boot_services
+            .expect_set_timer()
+            .withf(|event, timer_type, trigger_time| {
+                *event as usize == TIMER_EVENT
+                    && matches!(timer_type, EventTimerType::Relative)
+                    && *trigger_time == 10_000_000
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        boot_services.expect_signal_event().withf(|event| *event as usize == WAKE_EVENT).returning(|_| {
+            SIGNAL_COUNT.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        // Simulate the timer expiring while the executor is waiting.
+        boot_services.expect_wait_for_event().times(1).returning(|_| {
+            let (notify_function, notify_context) = NOTIFY.lock().unwrap().unwrap();
+            let notify_function: NotifyFunction = unsafe { mem::transmute(notify_function) };
+            notify_function(TIMER_EVENT as efi::Event, notify_context as *mut c_void);
+            Ok(0)
+        });
+        boot_services.expect_close_event().withf(|event| *event as usize == TIMER_EVENT).times(1).returning(|_| Ok(()));
+
+        let result = block_on(&boot_services, async {
+            sleep(&boot_services, Duration::from_secs(1))?.await;
+            Ok::<_, efi::Status>(())
+        });
+        assert_eq!(Ok(Ok(())), result);
+        assert_eq!(1, SIGNAL_COUNT.load(Ordering::SeqCst));
+        boot_services.checkpoint();
+    }
+}
@@ -1,16 +1,26 @@
 #![cfg_attr(all(not(test), not(feature = "mockall")), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
-#[cfg(feature = "global_allocator")]
+#[cfg(any(test, feature = "global_allocator"))]
 pub mod global_allocator;
 
+#[cfg(any(test, feature = "async"))]
+pub mod r#async;
+
 extern crate alloc;
 
 pub mod allocation;
+pub mod allocator;
 pub mod boxed;
+pub mod driver_model;
 pub mod event;
+pub mod handle;
+pub mod pages;
 pub mod protocol_handler;
+pub mod service_binding;
 pub mod static_ptr;
 pub mod tpl;
+pub mod tracking;
 
 #[cfg(any(test, feature = "mockall"))]
 use mockall::automock;
@@ -20,10 +30,11 @@ use core::{
     any::{Any, TypeId},
     ffi::c_void,
     marker::PhantomData,
-    mem::{self, MaybeUninit},
+    mem::{self, ManuallyDrop, MaybeUninit},
     option::Option,
-    ptr,
+    ptr::{self, NonNull},
     sync::atomic::{AtomicPtr, Ordering},
+    time::Duration,
 };
 use static_ptr::{StaticPtr, StaticPtrMut};
 
@@ -32,9 +43,14 @@ use r_efi::efi;
 use allocation::{AllocType, MemoryMap, MemoryType};
 use boxed::BootServicesBox;
 use event::{EventNotifyCallback, EventTimerType, EventType};
-use protocol_handler::{HandleSearchType, Protocol, Registration};
+use protocol_handler::{
+    HandleSearchType, OpenProtocolAttributes, OpenProtocolInformationEntry, Protocol, ProtocolInterfaces, Registration,
+};
 use tpl::{Tpl, TplGuard};
 
+/// Error returned by [`BootServices::start_image`], the status along with the exit data of the image, if any.
+pub type StartImageError<'a, B> = (efi::Status, Option<BootServicesBox<'a, [u16], B>>);
+
 /// This is the boot services used in the UEFI.
 /// it wraps an atomic ptr to [`efi::BootServices`]
 #[derive(Debug)]
@@ -71,13 +87,52 @@ impl<'a> StandardBootServices<'a> {
         }
     }
 
+    /// Terminates all boot services.
+    ///
+    /// If the key of *memory_map* is stale, a fresh memory map is fetched and the call is retried once.
+    /// On success, the final memory map is returned wrapped in a [`ManuallyDrop`] because its buffer can not be freed
+    /// anymore. After this call, any use of this StandardBootServices will panic.
+    ///
+    /// [UEFI Spec Documentation: 7.4.6. EFI_BOOT_SERVICES.ExitBootServices()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-exitbootservices)
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn exit_boot_services<'b>(
+        &'b self,
+        image_handle: efi::Handle,
+        memory_map: MemoryMap<'b, Self>,
+    ) -> Result<ManuallyDrop<MemoryMap<'b, Self>>, efi::Status> {
+        //SAFETY: The map key come from a memory map returned by get_memory_map.
+        match unsafe { self.exit_boot_services_unchecked(image_handle, memory_map.map_key) } {
+            Ok(()) => return Ok(ManuallyDrop::new(memory_map)),
+            Err(efi::Status::INVALID_PARAMETER) => (),
+            Err(status) => return Err(status),
+        }
+        // The memory map is out of date, only memory allocation services can be called at this point.
+        drop(memory_map);
+        let memory_map = self.get_memory_map().map_err(|(status, _)| status)?;
+        //SAFETY: The map key come from a memory map returned by get_memory_map.
+        unsafe { self.exit_boot_services_unchecked(image_handle, memory_map.map_key) }?;
+        Ok(ManuallyDrop::new(memory_map))
+    }
+
+    /// Return true if boot services have been terminated by a successful call to [Self::exit_boot_services].
+    pub fn is_exited(&self) -> bool {
+        self.efi_boot_services.load(Ordering::SeqCst) == Self::exited_marker()
+    }
+
+    /// Pointer value stored in place of the [efi::BootServices] once boot services have been exited.
+    fn exited_marker() -> *mut efi::BootServices {
+        NonNull::dangling().as_ptr()
+    }
+
     /// # Panics
-    /// This function will panic if it was not initialize.
+    /// This function will panic if it was not initialize or if boot services have been exited.
     fn efi_boot_services(&self) -> &efi::BootServices {
-        // SAFETY: This pointer is assume to be a valid efi::BootServices pointer since the only way to set it was via an efi::BootServices reference.
-        unsafe {
-            self.efi_boot_services.load(Ordering::SeqCst).as_ref::<'a>().expect("Boot services is not initialize.")
+        let efi_boot_services = self.efi_boot_services.load(Ordering::SeqCst);
+        if efi_boot_services == Self::exited_marker() {
+            panic!("Boot services is not available after exit boot services.")
         }
+        // SAFETY: This pointer is assume to be a valid efi::BootServices pointer since the only way to set it was via an efi::BootServices reference.
+        unsafe { efi_boot_services.as_ref::<'a>().expect("Boot services is not initialize.") }
     }
 }
 
@@ -103,6 +158,7 @@ pub trait BootServices: Sized {
         T: StaticPtr + 'static,
         <T as StaticPtr>::Pointee: Sized + 'static,
     {
+        event_type.validate()?;
         //SAFETY: ['StaticPtr`] generic is used to guaranteed that rust borowing and rules are meet.
         unsafe {
             self.create_event_unchecked(
@@ -149,6 +205,7 @@ pub trait BootServices: Sized {
         T: StaticPtr + 'static,
         <T as StaticPtr>::Pointee: Sized + 'static,
     {
+        event_type.validate_for_group()?;
         //SAFETY: [`StaticPtr`] generic is used to guaranteed that rust borowing and rules are meet.
         unsafe {
             self.create_event_ex_unchecked(
@@ -265,10 +322,7 @@ pub trait BootServices: Sized {
         protocol: &P,
         interface: &'static mut I,
     ) -> Result<efi::Handle, efi::Status> {
-        let interface_ptr = match (interface as &dyn Any).downcast_ref::<()>() {
-            Some(()) => ptr::null_mut(),
-            None => interface as *mut _ as *mut c_void,
-        };
+        let interface_ptr = protocol_handler::interface_ptr(interface);
         //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
         unsafe { self.install_protocol_interface_unchecked(handle, protocol.protocol_guid(), interface_ptr) }
     }
@@ -295,10 +349,7 @@ pub trait BootServices: Sized {
         protocol: &P,
         interface: &'static mut I,
     ) -> Result<(), efi::Status> {
-        let interface_ptr = match (interface as &dyn Any).downcast_ref::<()>() {
-            Some(()) => ptr::null_mut(),
-            None => interface as *mut _ as *mut c_void,
-        };
+        let interface_ptr = protocol_handler::interface_ptr(interface);
         //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
         unsafe { self.uninstall_protocol_interface_unchecked(handle, protocol.protocol_guid(), interface_ptr) }
     }
@@ -419,7 +470,7 @@ pub trait BootServices: Sized {
         protocol: &P,
         agent_handle: efi::Handle,
         controller_handle: efi::Handle,
-        attribute: u32,
+        attribute: OpenProtocolAttributes,
     ) -> Result<Option<&'static mut I>, efi::Status> {
         //SAFETY: The generic Protocol ensure that the interfaces is the right type for the specified protocol.
         unsafe {
@@ -439,7 +490,7 @@ pub trait BootServices: Sized {
         protocol: &efi::Guid,
         agent_handle: efi::Handle,
         controller_handle: efi::Handle,
-        attribute: u32,
+        attribute: OpenProtocolAttributes,
     ) -> Result<*mut c_void, efi::Status>;
 
     /// Closes a protocol on a handle that was previously opened.
@@ -460,7 +511,7 @@ pub trait BootServices: Sized {
         &'a self,
         handle: efi::Handle,
         protocol: &efi::Guid,
-    ) -> Result<BootServicesBox<'a, [efi::OpenProtocolInformationEntry], Self>, efi::Status>;
+    ) -> Result<BootServicesBox<'a, [OpenProtocolInformationEntry], Self>, efi::Status>;
 
     /// Connects one or more drivers to a controller.
     ///
@@ -531,6 +582,331 @@ pub trait BootServices: Sized {
         registration: *mut c_void,
     ) -> Result<*mut c_void, efi::Status>;
 
+    /// Installs one or more protocol interfaces into the boot services environment.
+    /// If the handle does not exist, it is created and added to the list of handles in the system.
+    ///
+    /// The protocols are installed at [`Tpl::NOTIFY`] and if one of them fails to install,
+    /// the ones already installed are uninstalled so the handle is left untouched.
+    /// If a device path is installed and an identical device path is already in the handle database,
+    /// nothing is installed and [`efi::Status::ALREADY_STARTED`] is returned.
+    ///
+    /// [UEFI Spec Documentation: 7.3.17. EFI_BOOT_SERVICES.InstallMultipleProtocolInterfaces()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-installmultipleprotocolinterfaces)
+    fn install_multiple_protocol_interfaces<T: ProtocolInterfaces + 'static>(
+        &self,
+        handle: Option<efi::Handle>,
+        interfaces: T,
+    ) -> Result<efi::Handle, efi::Status> {
+        //SAFETY: The ProtocolInterfaces trait ensure that the interfaces are the right type for their protocols.
+        unsafe { self.install_multiple_protocol_interfaces_unchecked(handle, &interfaces.into_raw_interfaces()) }
+    }
+
+    /// Prefer normal [`BootServices::install_multiple_protocol_interfaces`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that every non-null interface pointer is adhereing to
+    /// the structure associated with the protocol it is paired with.
+    unsafe fn install_multiple_protocol_interfaces_unchecked(
+        &self,
+        handle: Option<efi::Handle>,
+        interfaces: &[(&'static efi::Guid, *mut c_void)],
+    ) -> Result<efi::Handle, efi::Status> {
+        let _tpl_guard = self.raise_tpl_guarded(Tpl::NOTIFY);
+        for (protocol, interface) in interfaces.iter() {
+            if *protocol != &efi::protocols::device_path::PROTOCOL_GUID || interface.is_null() {
+                continue;
+            }
+            let mut device_path = *interface as *mut efi::protocols::device_path::Protocol;
+            if let Ok(device_handle) =
+                self.locate_device_path(&efi::protocols::device_path::PROTOCOL_GUID, ptr::addr_of_mut!(device_path))
+            {
+                // The whole device path matched one already installed.
+                if !device_handle.is_null()
+                    && (*device_path).r#type == efi::protocols::device_path::TYPE_END
+                    && (*device_path).sub_type == efi::protocols::device_path::End::SUBTYPE_ENTIRE
+                {
+                    return Err(efi::Status::ALREADY_STARTED);
+                }
+            }
+        }
+        let mut handle = handle;
+        for (idx, (protocol, interface)) in interfaces.iter().enumerate() {
+            match self.install_protocol_interface_unchecked(handle, protocol, *interface) {
+                Ok(new_handle) => handle = Some(new_handle),
+                Err(status) => {
+                    if let Some(handle) = handle {
+                        for (protocol, interface) in interfaces[..idx].iter().rev() {
+                            let _ = self.uninstall_protocol_interface_unchecked(handle, protocol, *interface);
+                        }
+                    }
+                    return Err(status);
+                }
+            }
+        }
+        handle.ok_or(efi::Status::INVALID_PARAMETER)
+    }
+
+    /// Removes one or more protocol interfaces from the boot services environment.
+    ///
+    /// The protocols are uninstalled at [`Tpl::NOTIFY`] and if one of them fails to uninstall,
+    /// the ones already uninstalled are reinstalled so the handle is left untouched.
+    ///
+    /// [UEFI Spec Documentation: 7.3.18. EFI_BOOT_SERVICES.UninstallMultipleProtocolInterfaces()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-uninstallmultipleprotocolinterfaces)
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn uninstall_multiple_protocol_interfaces<T: ProtocolInterfaces + 'static>(
+        &self,
+        handle: efi::Handle,
+        interfaces: T,
+    ) -> Result<(), efi::Status> {
+        //SAFETY: The ProtocolInterfaces trait ensure that the interfaces are the right type for their protocols.
+        unsafe { self.uninstall_multiple_protocol_interfaces_unchecked(handle, &interfaces.into_raw_interfaces()) }
+    }
+
+    /// Prefer normal [`BootServices::uninstall_multiple_protocol_interfaces`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that every non-null interface pointer is adhereing to
+    /// the structure associated with the protocol it is paired with.
+    unsafe fn uninstall_multiple_protocol_interfaces_unchecked(
+        &self,
+        handle: efi::Handle,
+        interfaces: &[(&'static efi::Guid, *mut c_void)],
+    ) -> Result<(), efi::Status> {
+        let _tpl_guard = self.raise_tpl_guarded(Tpl::NOTIFY);
+        for (idx, (protocol, interface)) in interfaces.iter().enumerate() {
+            if let Err(status) = self.uninstall_protocol_interface_unchecked(handle, protocol, *interface) {
+                for (protocol, interface) in interfaces[..idx].iter().rev() {
+                    let _ = self.install_protocol_interface_unchecked(Some(handle), protocol, *interface);
+                }
+                return Err(status);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads an EFI image into memory.
+    ///
+    /// The image is loaded from *source_buffer* when provided, otherwise it is loaded from the *device_path*.
+    ///
+    /// [UEFI Spec Documentation: 7.4.1. EFI_BOOT_SERVICES.LoadImage()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-loadimage)
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn load_image<'a>(
+        &self,
+        boot_policy: bool,
+        parent_image_handle: efi::Handle,
+        device_path: Option<&'a efi::protocols::device_path::Protocol>,
+        source_buffer: Option<&'a [u8]>,
+    ) -> Result<efi::Handle, efi::Status> {
+        let device_path = device_path.map_or(ptr::null_mut(), |d| d as *const _ as *mut _);
+        let (source_buffer, source_size) =
+            source_buffer.map_or((ptr::null_mut(), 0), |b| (b.as_ptr() as *mut c_void, b.len()));
+        //SAFETY: The device path and the source buffer come from references that outlive this call.
+        unsafe { self.load_image_unchecked(boot_policy, parent_image_handle, device_path, source_buffer, source_size) }
+    }
+
+    /// Prefer normal [`BootServices::load_image`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that:
+    /// * *device_path* is null or points to a valid device path.
+    /// * *source_buffer* is null or is valid for reads of *source_size* bytes.
+    unsafe fn load_image_unchecked(
+        &self,
+        boot_policy: bool,
+        parent_image_handle: efi::Handle,
+        device_path: *mut efi::protocols::device_path::Protocol,
+        source_buffer: *mut c_void,
+        source_size: usize,
+    ) -> Result<efi::Handle, efi::Status>;
+
+    /// Transfers control to a loaded image's entry point.
+    ///
+    /// If the image returns an error, the exit data it provided (if any) is returned along with the status.
+    /// Per the specification, the exit data is only valid when the status is not [`efi::Status::SUCCESS`].
+    ///
+    /// [UEFI Spec Documentation: 7.4.2. EFI_BOOT_SERVICES.StartImage()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-startimage)
+    // The explicit lifetime is needed by automock.
+    #[allow(clippy::needless_lifetimes, clippy::not_unsafe_ptr_arg_deref)]
+    fn start_image<'a>(&'a self, image_handle: efi::Handle) -> Result<(), StartImageError<'a, Self>> {
+        let mut exit_data_size = 0;
+        let mut exit_data = ptr::null_mut();
+        //SAFETY: The exit data size and exit data pointers are valid for writes.
+        match unsafe {
+            self.start_image_unchecked(image_handle, ptr::addr_of_mut!(exit_data_size), ptr::addr_of_mut!(exit_data))
+        } {
+            Ok(()) => Ok(()),
+            Err(status) if exit_data.is_null() => Err((status, None)),
+            Err(status) => {
+                //SAFETY: The exit data is allocated from pool by the started image and ownership is given to the caller.
+                let exit_data =
+                    unsafe { BootServicesBox::from_raw_parts(exit_data, exit_data_size / mem::size_of::<u16>(), self) };
+                Err((status, Some(exit_data)))
+            }
+        }
+    }
+
+    /// Prefer normal [`BootServices::start_image`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that *exit_data_size* and *exit_data* are null or valid for writes.
+    /// The caller is responsible to free the exit data returned.
+    unsafe fn start_image_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> Result<(), efi::Status>;
+
+    /// Unloads an image.
+    ///
+    /// [UEFI Spec Documentation: 7.4.3. EFI_BOOT_SERVICES.UnloadImage()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-unloadimage)
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn unload_image(&self, image_handle: efi::Handle) -> Result<(), efi::Status> {
+        //SAFETY: Only an handle is given to the firmware, the firmware is responsible to validate it.
+        unsafe { self.unload_image_unchecked(image_handle) }
+    }
+
+    /// Prefer normal [`BootServices::unload_image`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that nothing still reference the image code or data.
+    unsafe fn unload_image_unchecked(&self, image_handle: efi::Handle) -> Result<(), efi::Status>;
+
+    /// Terminates a loaded EFI image and returns control to boot services.
+    ///
+    /// The *exit_data* must be allocated from pool, this is why it is taken as a [`BootServicesBox`].
+    /// It must start with a null-terminated string, otherwise [`efi::Status::INVALID_PARAMETER`] is returned.
+    /// Its ownership is given to the caller of [`BootServices::start_image`], if the call fails, it is freed.
+    ///
+    /// [UEFI Spec Documentation: 7.4.5. EFI_BOOT_SERVICES.Exit()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-exit)
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn exit<'a>(
+        &'a self,
+        image_handle: efi::Handle,
+        exit_status: efi::Status,
+        exit_data: Option<BootServicesBox<'a, [u16], Self>>,
+    ) -> Result<(), efi::Status> {
+        let Some(exit_data) = exit_data else {
+            //SAFETY: No exit data is given to the firmware.
+            return unsafe { self.exit_unchecked(image_handle, exit_status, 0, ptr::null_mut()) };
+        };
+        if !exit_data.contains(&0) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let exit_data_size = mem::size_of_val(exit_data.as_ref());
+        let exit_data_len = exit_data.len();
+        let exit_data = exit_data.leak().as_mut_ptr();
+        //SAFETY: The exit data come from a BootServicesBox and is therefore allocated from pool
+        // and it contains a null-terminated string.
+        let status = unsafe { self.exit_unchecked(image_handle, exit_status, exit_data_size, exit_data) };
+        if status.is_err() {
+            //SAFETY: The firmware did not take ownership of the exit data, it is given back to a box to be freed.
+            drop(unsafe { BootServicesBox::from_raw_parts(exit_data, exit_data_len, self) });
+        }
+        status
+    }
+
+    /// Prefer normal [`BootServices::exit`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that *exit_data* is null or is a buffer of *exit_data_size* bytes
+    /// allocated with [`BootServices::allocate_pool`] that starts with a null-terminated string.
+    unsafe fn exit_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_status: efi::Status,
+        exit_data_size: usize,
+        exit_data: *mut u16,
+    ) -> Result<(), efi::Status>;
+
+    /// Terminates all boot services.
+    ///
+    /// Prefer [`StandardBootServices::exit_boot_services`] when possible.
+    ///
+    /// [UEFI Spec Documentation: 7.4.6. EFI_BOOT_SERVICES.ExitBootServices()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-exitbootservices)
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that *map_key* is the key of the current memory map and that
+    /// no boot services are used after a successful call.
+    unsafe fn exit_boot_services_unchecked(&self, image_handle: efi::Handle, map_key: usize)
+        -> Result<(), efi::Status>;
+
+    /// Sets the system's watchdog timer.
+    ///
+    /// The *timeout* is in seconds, a value of 0 disables the watchdog timer.
+    /// The *watchdog_data* is a null-terminated string optionally followed by binary data,
+    /// otherwise [`efi::Status::INVALID_PARAMETER`] is returned.
+    ///
+    /// [UEFI Spec Documentation: 7.5.1. EFI_BOOT_SERVICES.SetWatchdogTimer()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-setwatchdogtimer)
+    // The explicit lifetime is needed by automock.
+    #[allow(clippy::needless_lifetimes)]
+    fn set_watchdog_timer<'a>(
+        &self,
+        timeout: usize,
+        watchdog_code: u64,
+        watchdog_data: Option<&'a [u16]>,
+    ) -> Result<(), efi::Status>;
+
+    /// Induces a fine-grained stall.
+    ///
+    /// The duration is rounded down to the microsecond.
+    ///
+    /// [UEFI Spec Documentation: 7.5.2. EFI_BOOT_SERVICES.Stall()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-stall)
+    fn stall(&self, duration: Duration) -> Result<(), efi::Status>;
+
+    /// Copies the contents of one buffer to another buffer.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the two slices have different lengths.
+    ///
+    /// [UEFI Spec Documentation: 7.5.3. EFI_BOOT_SERVICES.CopyMem()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-copymem)
+    fn copy_mem(&self, destination: &mut [u8], source: &[u8]) {
+        assert_eq!(destination.len(), source.len(), "destination and source length mismatch.");
+        //SAFETY: Both buffers come from slices of the same length.
+        unsafe {
+            self.copy_mem_unchecked(
+                destination.as_mut_ptr() as *mut c_void,
+                source.as_ptr() as *const c_void,
+                source.len(),
+            )
+        }
+    }
+
+    /// Prefer normal [`BootServices::copy_mem`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that *destination* is valid for writes and *source* is valid for
+    /// reads of *length* bytes. The buffers may overlap.
+    unsafe fn copy_mem_unchecked(&self, destination: *mut c_void, source: *const c_void, length: usize);
+
+    /// Fills a buffer with a specified value.
+    ///
+    /// [UEFI Spec Documentation: 7.5.4. EFI_BOOT_SERVICES.SetMem()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-setmem)
+    fn set_mem(&self, buffer: &mut [u8], value: u8) {
+        //SAFETY: The buffer come from a slice.
+        unsafe { self.set_mem_unchecked(buffer.as_mut_ptr() as *mut c_void, buffer.len(), value) }
+    }
+
+    /// Prefer normal [`BootServices::set_mem`] when possible.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that *buffer* is valid for writes of *size* bytes.
+    unsafe fn set_mem_unchecked(&self, buffer: *mut c_void, size: usize, value: u8);
+
+    /// Returns a monotonically increasing count for the platform.
+    ///
+    /// [UEFI Spec Documentation: 7.5.5. EFI_BOOT_SERVICES.GetNextMonotonicCount()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-getnextmonotoniccount)
+    fn get_next_monotonic_count(&self) -> Result<u64, efi::Status>;
+
     /// Adds, updates, or removes a configuration table entry from the EFI System Table.
     ///
     /// [UEFI Spec Documentation: 7.5.6. EFI_BOOT_SERVICES.InstallConfigurationTable()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-installconfigurationtable)
@@ -551,6 +927,11 @@ pub trait BootServices: Sized {
         guid: &efi::Guid,
         table: *mut c_void,
     ) -> Result<(), efi::Status>;
+
+    /// Computes and returns a 32-bit CRC for a data buffer.
+    ///
+    /// [UEFI Spec Documentation: 7.5.7. EFI_BOOT_SERVICES.CalculateCrc32()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-calculatecrc32)
+    fn calculate_crc32(&self, data: &[u8]) -> Result<u32, efi::Status>;
 }
 
 impl BootServices for StandardBootServices<'_> {
@@ -696,18 +1077,13 @@ impl BootServices for StandardBootServices<'_> {
         }
 
         let mut memory_address = match alloc_type {
-            AllocType::Address(address) => address,
-            AllocType::MaxAddress(address) => address,
+            AllocType::Address(address) => address.as_u64(),
+            AllocType::MaxAddress(address) => address.as_u64(),
             _ => 0,
         };
-        match allocate_pages(
-            alloc_type.into(),
-            memory_type.into(),
-            nb_pages,
-            ptr::addr_of_mut!(memory_address) as *mut u64,
-        ) {
+        match allocate_pages(alloc_type.into(), memory_type.into(), nb_pages, ptr::addr_of_mut!(memory_address)) {
             s if s.is_error() => Err(s),
-            _ => Ok(memory_address),
+            _ => Ok(memory_address as usize),
         }
     }
 
@@ -744,11 +1120,14 @@ impl BootServices for StandardBootServices<'_> {
             _ => (),
         };
 
-        let buffer = self.allocate_pool(MemoryType::BOOT_SERVICES_DATA, memory_map_size).map_err(|s| (s, 0))?;
+        let buffer_size = memory_map_size;
+        let buffer = self.allocate_pool(MemoryType::BOOT_SERVICES_DATA, buffer_size).map_err(|s| (s, 0))?;
+        //SAFETY: The buffer has just been allocated with this size.
+        let mut buffer = unsafe { BootServicesBox::from_raw_parts(buffer, buffer_size, self) };
 
         match get_memory_map(
             ptr::addr_of_mut!(memory_map_size),
-            buffer as *mut _,
+            buffer.as_mut_ptr() as *mut _,
             ptr::addr_of_mut!(map_key),
             ptr::addr_of_mut!(descriptor_size),
             ptr::addr_of_mut!(descriptor_version),
@@ -757,11 +1136,7 @@ impl BootServices for StandardBootServices<'_> {
             s if s.is_error() => return Err((s, 0)),
             _ => (),
         }
-        Ok(MemoryMap {
-            descriptors: unsafe { BootServicesBox::from_raw_parts(buffer as *mut _, descriptor_size, self) },
-            map_key,
-            descriptor_version,
-        })
+        Ok(MemoryMap::new(buffer, memory_map_size, map_key, descriptor_size, descriptor_version))
     }
 
     fn allocate_pool(&self, memory_type: MemoryType, size: usize) -> Result<*mut u8, efi::Status> {
@@ -890,7 +1265,10 @@ impl BootServices for StandardBootServices<'_> {
             ptr::addr_of_mut!(buffer_size),
             buffer as *mut efi::Handle,
         ) {
-            s if s.is_error() => Err(s),
+            s if s.is_error() => {
+                let _ = self.free_pool(buffer);
+                Err(s)
+            }
             _ => Ok(unsafe {
                 BootServicesBox::from_raw_parts(buffer as *mut _, buffer_size / mem::size_of::<efi::Handle>(), &self)
             }),
@@ -935,7 +1313,7 @@ impl BootServices for StandardBootServices<'_> {
         protocol: &efi::Guid,
         agent_handle: efi::Handle,
         controller_handle: efi::Handle,
-        attribute: u32,
+        attribute: OpenProtocolAttributes,
     ) -> Result<*mut c_void, efi::Status> {
         let open_protocol = self.efi_boot_services().open_protocol;
         if open_protocol as usize == 0 {
@@ -948,7 +1326,7 @@ impl BootServices for StandardBootServices<'_> {
             ptr::addr_of_mut!(interface),
             agent_handle,
             controller_handle,
-            attribute,
+            attribute.into(),
         ) {
             s if s.is_error() => Err(s),
             _ => Ok(interface),
@@ -976,7 +1354,7 @@ impl BootServices for StandardBootServices<'_> {
         &self,
         handle: efi::Handle,
         protocol: &efi::Guid,
-    ) -> Result<BootServicesBox<[efi::OpenProtocolInformationEntry], Self>, efi::Status>
+    ) -> Result<BootServicesBox<[OpenProtocolInformationEntry], Self>, efi::Status>
     where
         Self: Sized,
     {
@@ -994,7 +1372,8 @@ impl BootServices for StandardBootServices<'_> {
             ptr::addr_of_mut!(entry_count),
         ) {
             s if s.is_error() => Err(s),
-            _ => Ok(unsafe { BootServicesBox::from_raw_parts(entry_buffer, entry_count, self) }),
+            // SAFETY: OpenProtocolInformationEntry has the same layout as efi::OpenProtocolInformationEntry.
+            _ => Ok(unsafe { BootServicesBox::from_raw_parts(entry_buffer as *mut _, entry_count, self) }),
         }
     }
 
@@ -1111,6 +1490,95 @@ impl BootServices for StandardBootServices<'_> {
         }
     }
 
+    unsafe fn load_image_unchecked(
+        &self,
+        boot_policy: bool,
+        parent_image_handle: efi::Handle,
+        device_path: *mut efi::protocols::device_path::Protocol,
+        source_buffer: *mut c_void,
+        source_size: usize,
+    ) -> Result<efi::Handle, efi::Status> {
+        let load_image = self.efi_boot_services().load_image;
+        if load_image as usize == 0 {
+            panic!("function not initialize.")
+        }
+        let mut image_handle = ptr::null_mut();
+        match load_image(
+            boot_policy.into(),
+            parent_image_handle,
+            device_path,
+            source_buffer,
+            source_size,
+            ptr::addr_of_mut!(image_handle),
+        ) {
+            s if s.is_error() => Err(s),
+            _ => Ok(image_handle),
+        }
+    }
+
+    unsafe fn start_image_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> Result<(), efi::Status> {
+        let start_image = self.efi_boot_services().start_image;
+        if start_image as usize == 0 {
+            panic!("function not initialize.")
+        }
+        match start_image(image_handle, exit_data_size, exit_data) {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    unsafe fn unload_image_unchecked(&self, image_handle: efi::Handle) -> Result<(), efi::Status> {
+        let unload_image = self.efi_boot_services().unload_image;
+        if unload_image as usize == 0 {
+            panic!("function not initialize.")
+        }
+        match unload_image(image_handle) {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    unsafe fn exit_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_status: efi::Status,
+        exit_data_size: usize,
+        exit_data: *mut u16,
+    ) -> Result<(), efi::Status> {
+        let exit = self.efi_boot_services().exit;
+        if exit as usize == 0 {
+            panic!("function not initialize.")
+        }
+        match exit(image_handle, exit_status, exit_data_size, exit_data) {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    unsafe fn exit_boot_services_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        map_key: usize,
+    ) -> Result<(), efi::Status> {
+        let exit_boot_services = self.efi_boot_services().exit_boot_services;
+        if exit_boot_services as usize == 0 {
+            panic!("function not initialize.")
+        }
+        match exit_boot_services(image_handle, map_key) {
+            s if s.is_error() => Err(s),
+            _ => {
+                // Boot services are gone, poison the pointer so any further use panics instead of calling into freed memory.
+                self.efi_boot_services.store(Self::exited_marker(), Ordering::SeqCst);
+                Ok(())
+            }
+        }
+    }
+
     unsafe fn install_configuration_table_unchecked(
         &self,
         guid: &efi::Guid,
@@ -1125,6 +1593,79 @@ impl BootServices for StandardBootServices<'_> {
             _ => Ok(()),
         }
     }
+
+    fn set_watchdog_timer(
+        &self,
+        timeout: usize,
+        watchdog_code: u64,
+        watchdog_data: Option<&[u16]>,
+    ) -> Result<(), efi::Status> {
+        let set_watchdog_timer = self.efi_boot_services().set_watchdog_timer;
+        if set_watchdog_timer as usize == 0 {
+            panic!("function not initialize.")
+        }
+        if watchdog_data.is_some_and(|d| !d.contains(&0)) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let (data_size, watchdog_data) =
+            watchdog_data.map_or((0, ptr::null_mut()), |d| (mem::size_of_val(d), d.as_ptr() as *mut efi::Char16));
+        match set_watchdog_timer(timeout, watchdog_code, data_size, watchdog_data) {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    fn stall(&self, duration: Duration) -> Result<(), efi::Status> {
+        let stall = self.efi_boot_services().stall;
+        if stall as usize == 0 {
+            panic!("function not initialize.")
+        }
+        let microseconds = usize::try_from(duration.as_micros()).unwrap_or(usize::MAX);
+        match stall(microseconds) {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    unsafe fn copy_mem_unchecked(&self, destination: *mut c_void, source: *const c_void, length: usize) {
+        let copy_mem = self.efi_boot_services().copy_mem;
+        if copy_mem as usize == 0 {
+            panic!("function not initialize.")
+        }
+        copy_mem(destination, source as *mut c_void, length)
+    }
+
+    unsafe fn set_mem_unchecked(&self, buffer: *mut c_void, size: usize, value: u8) {
+        let set_mem = self.efi_boot_services().set_mem;
+        if set_mem as usize == 0 {
+            panic!("function not initialize.")
+        }
+        set_mem(buffer, size, value)
+    }
+
+    fn get_next_monotonic_count(&self) -> Result<u64, efi::Status> {
+        let get_next_monotonic_count = self.efi_boot_services().get_next_monotonic_count;
+        if get_next_monotonic_count as usize == 0 {
+            panic!("function not initialize.")
+        }
+        let mut count = 0;
+        match get_next_monotonic_count(ptr::addr_of_mut!(count)) {
+            s if s.is_error() => Err(s),
+            _ => Ok(count),
+        }
+    }
+
+    fn calculate_crc32(&self, data: &[u8]) -> Result<u32, efi::Status> {
+        let calculate_crc32 = self.efi_boot_services().calculate_crc32;
+        if calculate_crc32 as usize == 0 {
+            panic!("function not initialize.")
+        }
+        let mut crc32 = 0;
+        match calculate_crc32(data.as_ptr() as *mut c_void, data.len(), ptr::addr_of_mut!(crc32)) {
+            s if s.is_error() => Err(s),
+            _ => Ok(crc32),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1132,7 +1673,12 @@ mod test {
     use efi;
 
     use super::*;
-    use core::{mem::MaybeUninit, sync::atomic::AtomicUsize};
+    use allocation::{MemoryAttribute, MemoryDescriptor, PhysicalAddress};
+    use core::{
+        mem::MaybeUninit,
+        slice,
+        sync::atomic::{AtomicBool, AtomicUsize},
+    };
 
     macro_rules! boot_services {
     ($($efi_services:ident = $efi_service_fn:ident),*) => {{
@@ -1536,7 +2082,7 @@ mod test {
             nb_pages: usize,
             memory: *mut u64,
         ) -> efi::Status {
-            let expected_alloc_type: efi::AllocateType = AllocType::Address(17).into();
+            let expected_alloc_type: efi::AllocateType = AllocType::Address(PhysicalAddress::new(17)).into();
             assert_eq!(expected_alloc_type, alloc_type);
             let expected_mem_type: efi::MemoryType = MemoryType::MEMORY_MAPPED_IO.into();
             assert_eq!(expected_mem_type, mem_type);
@@ -1546,7 +2092,8 @@ mod test {
             efi::Status::SUCCESS
         }
 
-        let status = boot_services.allocate_pages(AllocType::Address(17), MemoryType::MEMORY_MAPPED_IO, 4);
+        let status =
+            boot_services.allocate_pages(AllocType::Address(PhysicalAddress::new(17)), MemoryType::MEMORY_MAPPED_IO, 4);
         assert!(matches!(status, Ok(17)));
     }
 
@@ -1606,4 +2153,944 @@ mod test {
         let status = boot_services.free_pool(ptr::null_mut());
         assert_eq!(status, Err(efi::Status::INVALID_PARAMETER));
     }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_load_image_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.load_image(false, ptr::null_mut(), None, None);
+    }
+
+    #[test]
+    fn test_load_image_from_source_buffer() {
+        let boot_services = boot_services!(load_image = efi_load_image);
+
+        static SOURCE_BUFFER: [u8; 4] = [0x4D, 0x5A, 0x90, 0x00];
+
+        extern "efiapi" fn efi_load_image(
+            boot_policy: efi::Boolean,
+            parent_image_handle: efi::Handle,
+            device_path: *mut efi::protocols::device_path::Protocol,
+            source_buffer: *mut c_void,
+            source_size: usize,
+            image_handle: *mut efi::Handle,
+        ) -> efi::Status {
+            assert_eq!(efi::Boolean::FALSE, boot_policy);
+            assert_eq!(1, parent_image_handle as usize);
+            assert_eq!(ptr::null_mut(), device_path);
+            assert_eq!(SOURCE_BUFFER.as_ptr(), source_buffer as *const u8);
+            assert_eq!(4, source_size);
+            unsafe { ptr::write(image_handle, 2_usize as efi::Handle) };
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.load_image(false, 1_usize as efi::Handle, None, Some(&SOURCE_BUFFER));
+        assert_eq!(Ok(2_usize as efi::Handle), status);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_start_image_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.start_image(ptr::null_mut());
+    }
+
+    #[test]
+    fn test_start_image() {
+        let boot_services = boot_services!(start_image = efi_start_image);
+
+        extern "efiapi" fn efi_start_image(
+            image_handle: efi::Handle,
+            exit_data_size: *mut usize,
+            exit_data: *mut *mut efi::Char16,
+        ) -> efi::Status {
+            assert_eq!(1, image_handle as usize);
+            assert_ne!(ptr::null_mut(), exit_data_size);
+            assert_ne!(ptr::null_mut(), exit_data);
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.start_image(1_usize as efi::Handle);
+        assert!(matches!(status, Ok(())));
+    }
+
+    #[test]
+    fn test_start_image_with_exit_data() {
+        let boot_services = boot_services!(start_image = efi_start_image, free_pool = efi_free_pool);
+
+        static mut EXIT_DATA: [u16; 3] = [b'E' as u16, b'!' as u16, 0];
+
+        extern "efiapi" fn efi_start_image(
+            _image_handle: efi::Handle,
+            exit_data_size: *mut usize,
+            exit_data: *mut *mut efi::Char16,
+        ) -> efi::Status {
+            unsafe {
+                ptr::write(exit_data_size, mem::size_of::<[u16; 3]>());
+                ptr::write(exit_data, ptr::addr_of_mut!(EXIT_DATA) as *mut u16);
+            }
+            efi::Status::ABORTED
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(EXIT_DATA) } as *mut c_void, buffer);
+            efi::Status::SUCCESS
+        }
+
+        match boot_services.start_image(1_usize as efi::Handle) {
+            Err((status, Some(exit_data))) => {
+                assert_eq!(efi::Status::ABORTED, status);
+                assert_eq!(&[b'E' as u16, b'!' as u16, 0], exit_data.as_ref());
+            }
+            _ => panic!("start_image should have returned an error with exit data."),
+        }
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_unload_image_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.unload_image(ptr::null_mut());
+    }
+
+    #[test]
+    fn test_unload_image() {
+        let boot_services = boot_services!(unload_image = efi_unload_image);
+
+        extern "efiapi" fn efi_unload_image(image_handle: efi::Handle) -> efi::Status {
+            assert_eq!(1, image_handle as usize);
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.unload_image(1_usize as efi::Handle);
+        assert!(matches!(status, Ok(())));
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_exit_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.exit(ptr::null_mut(), efi::Status::SUCCESS, None);
+    }
+
+    #[test]
+    fn test_exit_with_exit_data() {
+        let boot_services = boot_services!(exit = efi_exit);
+
+        static mut EXIT_DATA: [u16; 3] = [b'E' as u16, b'!' as u16, 0];
+
+        extern "efiapi" fn efi_exit(
+            image_handle: efi::Handle,
+            exit_status: efi::Status,
+            exit_data_size: usize,
+            exit_data: *mut efi::Char16,
+        ) -> efi::Status {
+            assert_eq!(1, image_handle as usize);
+            assert_eq!(efi::Status::ABORTED, exit_status);
+            assert_eq!(mem::size_of::<[u16; 3]>(), exit_data_size);
+            assert_eq!(unsafe { ptr::addr_of_mut!(EXIT_DATA) } as *mut u16, exit_data);
+            efi::Status::SUCCESS
+        }
+
+        let exit_data =
+            unsafe { BootServicesBox::from_raw_parts(ptr::addr_of_mut!(EXIT_DATA) as *mut u16, 3, boot_services) };
+        let status = boot_services.exit(1_usize as efi::Handle, efi::Status::ABORTED, Some(exit_data));
+        assert!(matches!(status, Ok(())));
+    }
+
+    #[test]
+    fn test_exit_with_exit_data_not_null_terminated() {
+        let boot_services = boot_services!(exit = efi_exit, free_pool = efi_free_pool);
+
+        static mut EXIT_DATA: [u16; 2] = [b'E' as u16, b'!' as u16];
+
+        extern "efiapi" fn efi_exit(
+            _image_handle: efi::Handle,
+            _exit_status: efi::Status,
+            _exit_data_size: usize,
+            _exit_data: *mut efi::Char16,
+        ) -> efi::Status {
+            panic!("exit should not be called with exit data that is not null-terminated.")
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(EXIT_DATA) } as *mut c_void, buffer);
+            efi::Status::SUCCESS
+        }
+
+        let exit_data =
+            unsafe { BootServicesBox::from_raw_parts(ptr::addr_of_mut!(EXIT_DATA) as *mut u16, 2, boot_services) };
+        let status = boot_services.exit(1_usize as efi::Handle, efi::Status::ABORTED, Some(exit_data));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), status);
+    }
+
+    #[test]
+    fn test_exit_error_frees_exit_data() {
+        let boot_services = boot_services!(exit = efi_exit, free_pool = efi_free_pool);
+
+        static mut EXIT_DATA: [u16; 3] = [b'E' as u16, b'!' as u16, 0];
+        static FREED: AtomicBool = AtomicBool::new(false);
+
+        extern "efiapi" fn efi_exit(
+            _image_handle: efi::Handle,
+            _exit_status: efi::Status,
+            _exit_data_size: usize,
+            _exit_data: *mut efi::Char16,
+        ) -> efi::Status {
+            efi::Status::INVALID_PARAMETER
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(EXIT_DATA) } as *mut c_void, buffer);
+            FREED.store(true, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+
+        let exit_data =
+            unsafe { BootServicesBox::from_raw_parts(ptr::addr_of_mut!(EXIT_DATA) as *mut u16, 3, boot_services) };
+        let status = boot_services.exit(1_usize as efi::Handle, efi::Status::ABORTED, Some(exit_data));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), status);
+        assert!(FREED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_exit_boot_services_not_init() {
+        let boot_services = boot_services!();
+        let _ = unsafe { boot_services.exit_boot_services_unchecked(ptr::null_mut(), 0) };
+    }
+
+    #[test]
+    fn test_exit_boot_services_retry_on_stale_map_key() {
+        let boot_services = boot_services!(
+            get_memory_map = efi_get_memory_map,
+            allocate_pool = efi_allocate_pool,
+            free_pool = efi_free_pool,
+            exit_boot_services = efi_exit_boot_services
+        );
+
+        static MAP_KEY: AtomicUsize = AtomicUsize::new(1);
+        static mut BUFFER: [u64; 0x100] = [0; 0x100];
+
+        extern "efiapi" fn efi_get_memory_map(
+            memory_map_size: *mut usize,
+            memory_map: *mut efi::MemoryDescriptor,
+            map_key: *mut usize,
+            descriptor_size: *mut usize,
+            descriptor_version: *mut u32,
+        ) -> efi::Status {
+            unsafe {
+                ptr::write(memory_map_size, 0);
+                ptr::write(map_key, MAP_KEY.fetch_add(1, Ordering::SeqCst));
+                ptr::write(descriptor_size, mem::size_of::<efi::MemoryDescriptor>());
+                ptr::write(descriptor_version, efi::MEMORY_DESCRIPTOR_VERSION);
+            }
+            if memory_map.is_null() {
+                efi::Status::BUFFER_TOO_SMALL
+            } else {
+                efi::Status::SUCCESS
+            }
+        }
+
+        extern "efiapi" fn efi_allocate_pool(
+            _mem_type: efi::MemoryType,
+            _size: usize,
+            buffer: *mut *mut c_void,
+        ) -> efi::Status {
+            unsafe { ptr::write(buffer, ptr::addr_of_mut!(BUFFER) as *mut c_void) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(BUFFER) } as *mut c_void, buffer);
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_exit_boot_services(image_handle: efi::Handle, map_key: usize) -> efi::Status {
+            assert_eq!(1, image_handle as usize);
+            // Only the last memory map fetched is up to date.
+            if map_key + 1 == MAP_KEY.load(Ordering::SeqCst) {
+                efi::Status::SUCCESS
+            } else {
+                efi::Status::INVALID_PARAMETER
+            }
+        }
+
+        let memory_map = boot_services.get_memory_map().unwrap();
+        // Make the memory map stale.
+        MAP_KEY.fetch_add(1, Ordering::SeqCst);
+
+        let memory_map = boot_services.exit_boot_services(1_usize as efi::Handle, memory_map).unwrap();
+        assert_eq!(MAP_KEY.load(Ordering::SeqCst) - 1, memory_map.map_key);
+        assert!(boot_services.is_exited());
+    }
+
+    #[test]
+    #[should_panic = "Boot services is not available after exit boot services."]
+    fn test_boot_services_after_exit_boot_services() {
+        let boot_services = boot_services!(
+            get_memory_map = efi_get_memory_map,
+            allocate_pool = efi_allocate_pool,
+            exit_boot_services = efi_exit_boot_services
+        );
+
+        static mut BUFFER: [u64; 0x100] = [0; 0x100];
+
+        extern "efiapi" fn efi_get_memory_map(
+            memory_map_size: *mut usize,
+            memory_map: *mut efi::MemoryDescriptor,
+            map_key: *mut usize,
+            descriptor_size: *mut usize,
+            descriptor_version: *mut u32,
+        ) -> efi::Status {
+            unsafe {
+                ptr::write(memory_map_size, 0);
+                ptr::write(map_key, 1);
+                ptr::write(descriptor_size, mem::size_of::<efi::MemoryDescriptor>());
+                ptr::write(descriptor_version, efi::MEMORY_DESCRIPTOR_VERSION);
+            }
+            if memory_map.is_null() {
+                efi::Status::BUFFER_TOO_SMALL
+            } else {
+                efi::Status::SUCCESS
+            }
+        }
+
+        extern "efiapi" fn efi_allocate_pool(
+            _mem_type: efi::MemoryType,
+            _size: usize,
+            buffer: *mut *mut c_void,
+        ) -> efi::Status {
+            unsafe { ptr::write(buffer, ptr::addr_of_mut!(BUFFER) as *mut c_void) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_exit_boot_services(_image_handle: efi::Handle, map_key: usize) -> efi::Status {
+            assert_eq!(1, map_key);
+            efi::Status::SUCCESS
+        }
+
+        let memory_map = boot_services.get_memory_map().unwrap();
+        let _ = boot_services.exit_boot_services(1_usize as efi::Handle, memory_map).unwrap();
+
+        // Any call to boot services after exit boot services should panic.
+        boot_services.raise_tpl(Tpl::NOTIFY);
+    }
+
+    #[test]
+    fn test_exit_boot_services_error() {
+        let boot_services = boot_services!(exit_boot_services = efi_exit_boot_services);
+
+        extern "efiapi" fn efi_exit_boot_services(_image_handle: efi::Handle, _map_key: usize) -> efi::Status {
+            efi::Status::UNSUPPORTED
+        }
+
+        let status = unsafe { boot_services.exit_boot_services_unchecked(1_usize as efi::Handle, 0) };
+        assert_eq!(Err(efi::Status::UNSUPPORTED), status);
+        assert!(!boot_services.is_exited());
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_set_watchdog_timer_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.set_watchdog_timer(0, 0, None);
+    }
+
+    #[test]
+    fn test_set_watchdog_timer() {
+        let boot_services = boot_services!(set_watchdog_timer = efi_set_watchdog_timer);
+
+        static WATCHDOG_DATA: [u16; 3] = [b'W' as u16, b'D' as u16, 0];
+
+        extern "efiapi" fn efi_set_watchdog_timer(
+            timeout: usize,
+            watchdog_code: u64,
+            data_size: usize,
+            watchdog_data: *mut efi::Char16,
+        ) -> efi::Status {
+            assert_eq!(300, timeout);
+            assert_eq!(0x10000, watchdog_code);
+            assert_eq!(6, data_size);
+            assert_eq!(WATCHDOG_DATA.as_ptr(), watchdog_data as *const u16);
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.set_watchdog_timer(300, 0x10000, Some(&WATCHDOG_DATA));
+        assert_eq!(Ok(()), status);
+    }
+
+    #[test]
+    fn test_set_watchdog_timer_data_not_null_terminated() {
+        let boot_services = boot_services!(set_watchdog_timer = efi_set_watchdog_timer);
+
+        extern "efiapi" fn efi_set_watchdog_timer(
+            _timeout: usize,
+            _watchdog_code: u64,
+            _data_size: usize,
+            _watchdog_data: *mut efi::Char16,
+        ) -> efi::Status {
+            panic!("set_watchdog_timer should not be called with watchdog data that is not null-terminated.")
+        }
+
+        let status = boot_services.set_watchdog_timer(300, 0x10000, Some(&[b'W' as u16, b'D' as u16]));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), status);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_stall_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.stall(Duration::ZERO);
+    }
+
+    #[test]
+    fn test_stall() {
+        let boot_services = boot_services!(stall = efi_stall);
+
+        extern "efiapi" fn efi_stall(microseconds: usize) -> efi::Status {
+            assert_eq!(1500, microseconds);
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.stall(Duration::from_nanos(1_500_999));
+        assert_eq!(Ok(()), status);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_copy_mem_not_init() {
+        let boot_services = boot_services!();
+        boot_services.copy_mem(&mut [], &[]);
+    }
+
+    #[test]
+    fn test_copy_mem() {
+        let boot_services = boot_services!(copy_mem = efi_copy_mem);
+
+        extern "efiapi" fn efi_copy_mem(destination: *mut c_void, source: *mut c_void, length: usize) {
+            unsafe { ptr::copy(source as *const u8, destination as *mut u8, length) }
+        }
+
+        let mut destination = [0_u8; 4];
+        boot_services.copy_mem(&mut destination, &[1, 2, 3, 4]);
+        assert_eq!([1, 2, 3, 4], destination);
+    }
+
+    #[test]
+    #[should_panic = "destination and source length mismatch."]
+    fn test_copy_mem_length_mismatch() {
+        let boot_services = boot_services!();
+        boot_services.copy_mem(&mut [0; 2], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_set_mem_not_init() {
+        let boot_services = boot_services!();
+        boot_services.set_mem(&mut [], 0);
+    }
+
+    #[test]
+    fn test_set_mem() {
+        let boot_services = boot_services!(set_mem = efi_set_mem);
+
+        extern "efiapi" fn efi_set_mem(buffer: *mut c_void, size: usize, value: u8) {
+            unsafe { ptr::write_bytes(buffer as *mut u8, value, size) }
+        }
+
+        let mut buffer = [0_u8; 4];
+        boot_services.set_mem(&mut buffer, 0xAA);
+        assert_eq!([0xAA; 4], buffer);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_get_next_monotonic_count_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.get_next_monotonic_count();
+    }
+
+    #[test]
+    fn test_get_next_monotonic_count() {
+        let boot_services = boot_services!(get_next_monotonic_count = efi_get_next_monotonic_count);
+
+        extern "efiapi" fn efi_get_next_monotonic_count(count: *mut u64) -> efi::Status {
+            unsafe { ptr::write(count, 42) };
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.get_next_monotonic_count();
+        assert_eq!(Ok(42), status);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_calculate_crc32_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.calculate_crc32(&[]);
+    }
+
+    #[test]
+    fn test_calculate_crc32() {
+        let boot_services = boot_services!(calculate_crc32 = efi_calculate_crc32);
+
+        extern "efiapi" fn efi_calculate_crc32(data: *mut c_void, data_size: usize, crc32: *mut u32) -> efi::Status {
+            if data_size == 0 {
+                return efi::Status::INVALID_PARAMETER;
+            }
+            let data = unsafe { slice::from_raw_parts(data as *const u8, data_size) };
+            assert_eq!(b"123456789", data);
+            unsafe { ptr::write(crc32, 0xCBF43926) };
+            efi::Status::SUCCESS
+        }
+
+        assert_eq!(Ok(0xCBF43926), boot_services.calculate_crc32(b"123456789"));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), boot_services.calculate_crc32(&[]));
+    }
+
+    #[test]
+    fn test_install_multiple_protocol_interfaces() {
+        let boot_services = boot_services!(
+            raise_tpl = efi_raise_tpl,
+            restore_tpl = efi_restore_tpl,
+            locate_device_path = efi_locate_device_path,
+            install_protocol_interface = efi_install_protocol_interface
+        );
+
+        static INSTALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        extern "efiapi" fn efi_raise_tpl(tpl: efi::Tpl) -> efi::Tpl {
+            assert_eq!(efi::TPL_NOTIFY, tpl);
+            efi::TPL_APPLICATION
+        }
+
+        extern "efiapi" fn efi_restore_tpl(tpl: efi::Tpl) {
+            assert_eq!(efi::TPL_APPLICATION, tpl);
+        }
+
+        extern "efiapi" fn efi_locate_device_path(
+            _protocol: *mut efi::Guid,
+            _device_path: *mut *mut efi::protocols::device_path::Protocol,
+            _device: *mut efi::Handle,
+        ) -> efi::Status {
+            efi::Status::NOT_FOUND
+        }
+
+        extern "efiapi" fn efi_install_protocol_interface(
+            handle: *mut efi::Handle,
+            protocol: *mut efi::Guid,
+            interface_type: efi::InterfaceType,
+            interface: *mut c_void,
+        ) -> efi::Status {
+            assert_eq!(efi::NATIVE_INTERFACE, interface_type);
+            assert_ne!(ptr::null_mut(), interface);
+            match INSTALL_COUNT.fetch_add(1, Ordering::SeqCst) {
+                0 => {
+                    assert_eq!(ptr::null_mut(), unsafe { *handle });
+                    assert_eq!(&efi::protocols::device_path::PROTOCOL_GUID, unsafe { &*protocol });
+                    unsafe { ptr::write(handle, 1_usize as efi::Handle) };
+                }
+                _ => {
+                    assert_eq!(1, unsafe { *handle } as usize);
+                    assert_eq!(&efi::protocols::loaded_image::PROTOCOL_GUID, unsafe { &*protocol });
+                }
+            }
+            efi::Status::SUCCESS
+        }
+
+        let device_path = Box::leak(Box::new(efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        }));
+        let loaded_image = Box::leak(Box::new(unsafe {
+            MaybeUninit::<efi::protocols::loaded_image::Protocol>::zeroed().assume_init()
+        }));
+
+        let status = boot_services.install_multiple_protocol_interfaces(
+            None,
+            ((&protocol_handler::DevicePath, device_path), (&protocol_handler::LoadedImage, loaded_image)),
+        );
+        assert_eq!(Ok(1_usize as efi::Handle), status);
+        assert_eq!(2, INSTALL_COUNT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_install_multiple_protocol_interfaces_rollback() {
+        let boot_services = boot_services!(
+            raise_tpl = efi_raise_tpl,
+            restore_tpl = efi_restore_tpl,
+            locate_device_path = efi_locate_device_path,
+            install_protocol_interface = efi_install_protocol_interface,
+            uninstall_protocol_interface = efi_uninstall_protocol_interface
+        );
+
+        static UNINSTALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        extern "efiapi" fn efi_raise_tpl(_tpl: efi::Tpl) -> efi::Tpl {
+            efi::TPL_APPLICATION
+        }
+
+        extern "efiapi" fn efi_restore_tpl(_tpl: efi::Tpl) {}
+
+        extern "efiapi" fn efi_locate_device_path(
+            _protocol: *mut efi::Guid,
+            _device_path: *mut *mut efi::protocols::device_path::Protocol,
+            _device: *mut efi::Handle,
+        ) -> efi::Status {
+            efi::Status::NOT_FOUND
+        }
+
+        extern "efiapi" fn efi_install_protocol_interface(
+            handle: *mut efi::Handle,
+            protocol: *mut efi::Guid,
+            _interface_type: efi::InterfaceType,
+            _interface: *mut c_void,
+        ) -> efi::Status {
+            if unsafe { &*protocol } == &efi::protocols::loaded_image::PROTOCOL_GUID {
+                return efi::Status::INVALID_PARAMETER;
+            }
+            unsafe { ptr::write(handle, 1_usize as efi::Handle) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_uninstall_protocol_interface(
+            handle: efi::Handle,
+            protocol: *mut efi::Guid,
+            _interface: *mut c_void,
+        ) -> efi::Status {
+            assert_eq!(1, handle as usize);
+            assert_eq!(&efi::protocols::device_path::PROTOCOL_GUID, unsafe { &*protocol });
+            UNINSTALL_COUNT.fetch_add(1, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+
+        let device_path = Box::leak(Box::new(efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        }));
+        let loaded_image = Box::leak(Box::new(unsafe {
+            MaybeUninit::<efi::protocols::loaded_image::Protocol>::zeroed().assume_init()
+        }));
+
+        let status = boot_services.install_multiple_protocol_interfaces(
+            None,
+            ((&protocol_handler::DevicePath, device_path), (&protocol_handler::LoadedImage, loaded_image)),
+        );
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), status);
+        assert_eq!(1, UNINSTALL_COUNT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_install_multiple_protocol_interfaces_already_started() {
+        let boot_services = boot_services!(
+            raise_tpl = efi_raise_tpl,
+            restore_tpl = efi_restore_tpl,
+            locate_device_path = efi_locate_device_path,
+            install_protocol_interface = efi_install_protocol_interface
+        );
+
+        extern "efiapi" fn efi_raise_tpl(_tpl: efi::Tpl) -> efi::Tpl {
+            efi::TPL_APPLICATION
+        }
+
+        extern "efiapi" fn efi_restore_tpl(_tpl: efi::Tpl) {}
+
+        extern "efiapi" fn efi_locate_device_path(
+            protocol: *mut efi::Guid,
+            device_path: *mut *mut efi::protocols::device_path::Protocol,
+            device: *mut efi::Handle,
+        ) -> efi::Status {
+            assert_eq!(&efi::protocols::device_path::PROTOCOL_GUID, unsafe { &*protocol });
+            // The device path is only an end node, it is entirely matched without moving forward.
+            assert_eq!(efi::protocols::device_path::TYPE_END, unsafe { (**device_path).r#type });
+            unsafe { ptr::write(device, 2_usize as efi::Handle) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_install_protocol_interface(
+            _handle: *mut efi::Handle,
+            _protocol: *mut efi::Guid,
+            _interface_type: efi::InterfaceType,
+            _interface: *mut c_void,
+        ) -> efi::Status {
+            panic!("Nothing should be installed when the device path is already in the handle database.")
+        }
+
+        let device_path = Box::leak(Box::new(efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        }));
+        let loaded_image = Box::leak(Box::new(unsafe {
+            MaybeUninit::<efi::protocols::loaded_image::Protocol>::zeroed().assume_init()
+        }));
+
+        let status = boot_services.install_multiple_protocol_interfaces(
+            None,
+            ((&protocol_handler::LoadedImage, loaded_image), (&protocol_handler::DevicePath, device_path)),
+        );
+        assert_eq!(Err(efi::Status::ALREADY_STARTED), status);
+    }
+
+    #[test]
+    fn test_uninstall_multiple_protocol_interfaces_rollback() {
+        let boot_services = boot_services!(
+            raise_tpl = efi_raise_tpl,
+            restore_tpl = efi_restore_tpl,
+            install_protocol_interface = efi_install_protocol_interface,
+            uninstall_protocol_interface = efi_uninstall_protocol_interface
+        );
+
+        static REINSTALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        extern "efiapi" fn efi_raise_tpl(_tpl: efi::Tpl) -> efi::Tpl {
+            efi::TPL_APPLICATION
+        }
+
+        extern "efiapi" fn efi_restore_tpl(_tpl: efi::Tpl) {}
+
+        extern "efiapi" fn efi_install_protocol_interface(
+            handle: *mut efi::Handle,
+            protocol: *mut efi::Guid,
+            _interface_type: efi::InterfaceType,
+            _interface: *mut c_void,
+        ) -> efi::Status {
+            assert_eq!(1, unsafe { *handle } as usize);
+            assert_eq!(&efi::protocols::device_path::PROTOCOL_GUID, unsafe { &*protocol });
+            REINSTALL_COUNT.fetch_add(1, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_uninstall_protocol_interface(
+            _handle: efi::Handle,
+            protocol: *mut efi::Guid,
+            _interface: *mut c_void,
+        ) -> efi::Status {
+            if unsafe { &*protocol } == &efi::protocols::loaded_image::PROTOCOL_GUID {
+                return efi::Status::ACCESS_DENIED;
+            }
+            efi::Status::SUCCESS
+        }
+
+        let device_path = Box::leak(Box::new(efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        }));
+        let loaded_image = Box::leak(Box::new(unsafe {
+            MaybeUninit::<efi::protocols::loaded_image::Protocol>::zeroed().assume_init()
+        }));
+
+        let status = boot_services.uninstall_multiple_protocol_interfaces(
+            1_usize as efi::Handle,
+            ((&protocol_handler::DevicePath, device_path), (&protocol_handler::LoadedImage, loaded_image)),
+        );
+        assert_eq!(Err(efi::Status::ACCESS_DENIED), status);
+        assert_eq!(1, REINSTALL_COUNT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_locate_handle_error_frees_buffer() {
+        let boot_services = boot_services!(
+            locate_handle = efi_locate_handle,
+            allocate_pool = efi_allocate_pool,
+            free_pool = efi_free_pool
+        );
+
+        static mut BUFFER: [efi::Handle; 2] = [ptr::null_mut(); 2];
+        static FREED: AtomicBool = AtomicBool::new(false);
+
+        extern "efiapi" fn efi_locate_handle(
+            _search_type: efi::LocateSearchType,
+            _protocol: *mut efi::Guid,
+            _search_key: *mut c_void,
+            buffer_size: *mut usize,
+            buffer: *mut efi::Handle,
+        ) -> efi::Status {
+            if buffer.is_null() {
+                unsafe { ptr::write(buffer_size, mem::size_of::<[efi::Handle; 2]>()) };
+                efi::Status::BUFFER_TOO_SMALL
+            } else {
+                // The handles have been uninstalled between the two calls.
+                efi::Status::NOT_FOUND
+            }
+        }
+
+        extern "efiapi" fn efi_allocate_pool(
+            _mem_type: efi::MemoryType,
+            _size: usize,
+            buffer: *mut *mut c_void,
+        ) -> efi::Status {
+            unsafe { ptr::write(buffer, ptr::addr_of_mut!(BUFFER) as *mut c_void) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(BUFFER) } as *mut c_void, buffer);
+            FREED.store(true, Ordering::SeqCst);
+            efi::Status::SUCCESS
+        }
+
+        let status = boot_services.locate_handle(HandleSearchType::AllHandle);
+        assert!(matches!(status, Err(efi::Status::NOT_FOUND)));
+        assert!(FREED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_open_protocol_information_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.open_protocol_information(ptr::null_mut(), &efi::protocols::device_path::PROTOCOL_GUID);
+    }
+
+    #[test]
+    fn test_open_protocol_information() {
+        let boot_services =
+            boot_services!(open_protocol_information = efi_open_protocol_information, free_pool = efi_free_pool);
+
+        static mut ENTRIES: [efi::OpenProtocolInformationEntry; 2] = [
+            efi::OpenProtocolInformationEntry {
+                agent_handle: 2_usize as efi::Handle,
+                controller_handle: 1_usize as efi::Handle,
+                attributes: efi::OPEN_PROTOCOL_BY_DRIVER,
+                open_count: 1,
+            },
+            efi::OpenProtocolInformationEntry {
+                agent_handle: 3_usize as efi::Handle,
+                controller_handle: 4_usize as efi::Handle,
+                attributes: efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER,
+                open_count: 2,
+            },
+        ];
+
+        extern "efiapi" fn efi_open_protocol_information(
+            handle: efi::Handle,
+            protocol: *mut efi::Guid,
+            entry_buffer: *mut *mut efi::OpenProtocolInformationEntry,
+            entry_count: *mut usize,
+        ) -> efi::Status {
+            assert_eq!(1, handle as usize);
+            assert_eq!(&efi::protocols::device_path::PROTOCOL_GUID, unsafe { &*protocol });
+            unsafe {
+                ptr::write(entry_buffer, ptr::addr_of_mut!(ENTRIES) as *mut _);
+                ptr::write(entry_count, 2);
+            }
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(ENTRIES) } as *mut c_void, buffer);
+            efi::Status::SUCCESS
+        }
+
+        let entries = boot_services
+            .open_protocol_information(1_usize as efi::Handle, &efi::protocols::device_path::PROTOCOL_GUID)
+            .unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(2, entries[0].agent_handle as usize);
+        assert!(entries[0].is_by_driver());
+        assert_eq!(4, entries[1].controller_handle as usize);
+        assert_eq!(OpenProtocolAttributes::BY_CHILD_CONTROLLER, entries[1].attributes);
+        assert_eq!(2, entries[1].open_count);
+    }
+
+    #[test]
+    #[should_panic = "function not initialize."]
+    fn test_get_memory_map_not_init() {
+        let boot_services = boot_services!();
+        let _ = boot_services.get_memory_map();
+    }
+
+    #[test]
+    fn test_get_memory_map_with_padded_descriptors() {
+        let boot_services = boot_services!(
+            get_memory_map = efi_get_memory_map,
+            allocate_pool = efi_allocate_pool,
+            free_pool = efi_free_pool
+        );
+
+        // Descriptors are padded by the firmware, the stride is bigger than efi::MemoryDescriptor.
+        const DESCRIPTOR_SIZE: usize = mem::size_of::<efi::MemoryDescriptor>() + 16;
+        const DESCRIPTORS: [efi::MemoryDescriptor; 3] = [
+            efi::MemoryDescriptor {
+                r#type: efi::CONVENTIONAL_MEMORY,
+                physical_start: 0x1000,
+                virtual_start: 0,
+                number_of_pages: 0x10,
+                attribute: efi::MEMORY_WB,
+            },
+            efi::MemoryDescriptor {
+                r#type: efi::BOOT_SERVICES_DATA,
+                physical_start: 0x11000,
+                virtual_start: 0,
+                number_of_pages: 0x2,
+                attribute: efi::MEMORY_WB | efi::MEMORY_XP,
+            },
+            efi::MemoryDescriptor {
+                r#type: efi::RUNTIME_SERVICES_CODE,
+                physical_start: 0x13000,
+                virtual_start: 0x13000,
+                number_of_pages: 0x1,
+                attribute: efi::MEMORY_WB | efi::MEMORY_RUNTIME,
+            },
+        ];
+        static mut BUFFER: [u64; 0x100] = [0; 0x100];
+
+        extern "efiapi" fn efi_get_memory_map(
+            memory_map_size: *mut usize,
+            memory_map: *mut efi::MemoryDescriptor,
+            map_key: *mut usize,
+            descriptor_size: *mut usize,
+            descriptor_version: *mut u32,
+        ) -> efi::Status {
+            let size = DESCRIPTORS.len() * DESCRIPTOR_SIZE;
+            unsafe {
+                let buffer_size = ptr::replace(memory_map_size, size);
+                ptr::write(descriptor_size, DESCRIPTOR_SIZE);
+                ptr::write(descriptor_version, efi::MEMORY_DESCRIPTOR_VERSION);
+                if buffer_size < size {
+                    return efi::Status::BUFFER_TOO_SMALL;
+                }
+                ptr::write(map_key, 42);
+                // Fill the padding with garbage to make sure it is not read.
+                ptr::write_bytes(memory_map as *mut u8, 0xFF, size);
+                for (i, descriptor) in DESCRIPTORS.iter().enumerate() {
+                    ptr::write_unaligned((memory_map as *mut u8).add(i * DESCRIPTOR_SIZE) as *mut _, *descriptor);
+                }
+            }
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_allocate_pool(
+            _mem_type: efi::MemoryType,
+            size: usize,
+            buffer: *mut *mut c_void,
+        ) -> efi::Status {
+            assert!(size <= mem::size_of::<[u64; 0x100]>());
+            unsafe { ptr::write(buffer, ptr::addr_of_mut!(BUFFER) as *mut c_void) };
+            efi::Status::SUCCESS
+        }
+
+        extern "efiapi" fn efi_free_pool(buffer: *mut c_void) -> efi::Status {
+            assert_eq!(unsafe { ptr::addr_of_mut!(BUFFER) } as *mut c_void, buffer);
+            efi::Status::SUCCESS
+        }
+
+        let memory_map = boot_services.get_memory_map().unwrap();
+        assert_eq!(42, memory_map.map_key);
+        assert_eq!(efi::MEMORY_DESCRIPTOR_VERSION, memory_map.descriptor_version);
+        assert_eq!(DESCRIPTOR_SIZE, memory_map.descriptor_size());
+        assert_eq!(3 * DESCRIPTOR_SIZE, memory_map.map_size());
+        assert_eq!(3, memory_map.len());
+        assert_eq!(3, memory_map.iter().len());
+        for (descriptor, expected) in memory_map.iter().zip(DESCRIPTORS) {
+            assert_eq!(MemoryDescriptor::from(expected), descriptor);
+        }
+        assert_eq!(MemoryType::RUNTIME_SERVICES_CODE, memory_map.get(2).unwrap().memory_type);
+        assert_eq!(MemoryAttribute::WB | MemoryAttribute::RUNTIME, memory_map.get(2).unwrap().attribute);
+        assert_eq!(None, memory_map.get(3));
+    }
 }
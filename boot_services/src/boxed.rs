@@ -1,36 +1,117 @@
-use alloc::slice;
 use core::{
-    mem,
+    alloc::Layout,
+    fmt,
+    mem::{self, MaybeUninit},
     ops::{Deref, DerefMut},
-    ptr,
+    ptr::{self, NonNull},
 };
 
-use crate::{allocation::MemoryType, BootServices};
+use r_efi::efi;
 
-#[derive(Debug)]
+use crate::{
+    allocation::MemoryType,
+    allocator::BootServicesAllocator,
+    static_ptr::{StaticPtr, StaticPtrMut},
+    BootServices,
+};
+
+/// Box allocated in pool memory, its content is dropped and the memory freed when the box is dropped.
 pub struct BootServicesBox<'a, T: ?Sized, B: BootServices> {
     ptr: *mut T,
+    memory_type: MemoryType,
+    /// The pointer was returned by [`BootServices::allocate_pool`] and is freed with [`BootServices::free_pool`],
+    /// otherwise it was allocated with [`BootServicesAllocator`].
+    adopted: bool,
     boot_services: &'a B,
 }
 
 impl<'a, T, B: BootServices> BootServicesBox<'a, T, B> {
+    /// Allocate pool memory of *memory_type* and move *value* into it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocation fails, see [`Self::try_new`].
     pub fn new(value: T, memory_type: MemoryType, boot_services: &'a B) -> Self {
-        let size = mem::size_of_val(&value);
-        let ptr = boot_services.allocate_pool(memory_type, size).unwrap() as *mut T;
-        unsafe { ptr::write(ptr, value) };
-        Self { boot_services, ptr }
+        Self::try_new(value, memory_type, boot_services).unwrap()
+    }
+
+    /// Allocate pool memory of *memory_type* and move *value* into it.
+    pub fn try_new(value: T, memory_type: MemoryType, boot_services: &'a B) -> Result<Self, efi::Status> {
+        Ok(BootServicesBox::new_uninit(memory_type, boot_services)?.write(value))
+    }
+
+    /// Allocate uninitialized pool memory for a `T`.
+    pub fn new_uninit(
+        memory_type: MemoryType,
+        boot_services: &'a B,
+    ) -> Result<BootServicesBox<'a, MaybeUninit<T>, B>, efi::Status> {
+        let ptr = allocate(Layout::new::<T>(), memory_type, boot_services)?;
+        Ok(BootServicesBox { ptr: ptr as *mut MaybeUninit<T>, memory_type, adopted: false, boot_services })
+    }
+
+    /// Allocate pool memory for a `T` filled with zeroes.
+    pub fn new_zeroed(
+        memory_type: MemoryType,
+        boot_services: &'a B,
+    ) -> Result<BootServicesBox<'a, MaybeUninit<T>, B>, efi::Status> {
+        let mut boxed = Self::new_uninit(memory_type, boot_services)?;
+        unsafe { ptr::write_bytes(boxed.as_mut_ptr(), 0, 1) };
+        Ok(boxed)
+    }
+}
+
+impl<'a, T, B: BootServices> BootServicesBox<'a, MaybeUninit<T>, B> {
+    /// Write *value* in the box and return it initialized.
+    pub fn write(mut self, value: T) -> BootServicesBox<'a, T, B> {
+        (*self).write(value);
+        unsafe { self.assume_init() }
+    }
+
+    /// # Safety
+    ///
+    /// The content of the box must have been initialized.
+    pub unsafe fn assume_init(self) -> BootServicesBox<'a, T, B> {
+        let (ptr, memory_type, adopted, boot_services) = (self.ptr, self.memory_type, self.adopted, self.boot_services);
+        mem::forget(self);
+        BootServicesBox { ptr: ptr as *mut T, memory_type, adopted, boot_services }
+    }
+}
+
+impl<'a, T: ?Sized, B: BootServices> BootServicesBox<'a, T, B> {
+    /// Take ownership of a pointer returned by [`BootServices::allocate_pool`], like the buffers returned by boot
+    /// services. The memory is freed with [`BootServices::free_pool`].
+    ///
+    /// The memory type of the pool is not known, [`Self::memory_type`] reports [`MemoryType::BOOT_SERVICES_DATA`]
+    /// and it is the type used to allocate the clones of the box.
+    ///
+    /// # Safety
+    ///
+    /// *ptr* must be returned by [`BootServices::allocate_pool`] for a valid `T`, and must not be used afterward.
+    /// Use [`Self::from_raw`] for a pointer coming from [`Self::into_raw`].
+    pub unsafe fn from_raw_pool(ptr: *mut T, boot_services: &'a B) -> Self {
+        Self { ptr, memory_type: MemoryType::BOOT_SERVICES_DATA, adopted: true, boot_services }
     }
 
-    pub unsafe fn from_raw(ptr: *mut T, boot_services: &'a B) -> Self {
-        Self { boot_services, ptr }
+    /// Take back the ownership of a pointer given up with [`Self::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// *ptr* must come from [`Self::into_raw`] of a box of *memory_type* and must not be used afterward.
+    pub unsafe fn from_raw(ptr: *mut T, memory_type: MemoryType, boot_services: &'a B) -> Self {
+        Self { ptr, memory_type, adopted: false, boot_services }
     }
 
-    pub unsafe fn into_raw(self) -> *const T {
-        self.ptr as *const T
+    /// Give up the ownership of the box, the pointer can be turned back into a box with [`Self::from_raw`] and the
+    /// same memory type.
+    pub fn into_raw(self) -> *mut T {
+        let ptr = self.ptr;
+        mem::forget(self);
+        ptr
     }
 
-    pub unsafe fn into_raw_mut(self) -> *mut T {
-        self.ptr
+    /// Same as [`Self::into_raw`].
+    pub fn into_raw_mut(self) -> *mut T {
+        self.into_raw()
     }
 
     pub fn leak(self) -> &'a mut T {
@@ -38,18 +119,74 @@ impl<'a, T, B: BootServices> BootServicesBox<'a, T, B> {
         mem::forget(self);
         leak
     }
+
+    /// Type of the pool memory, used to allocate the clones of the box.
+    pub fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
 }
 
 impl<'a, T, B: BootServices> BootServicesBox<'a, [T], B> {
+    /// Take ownership of a slice allocated in pool memory of type [`MemoryType::BOOT_SERVICES_DATA`].
+    ///
+    /// An empty slice is freed right away.
+    ///
+    /// # Safety
+    ///
+    /// *ptr* must be allocated with [`BootServices::allocate_pool`] for *len* valid `T`, and must not be used
+    /// afterward.
     pub unsafe fn from_raw_parts(ptr: *mut T, len: usize, boot_services: &'a B) -> Self {
-        let ptr = slice::from_raw_parts_mut(ptr, len) as *mut [T];
-        Self { boot_services, ptr }
+        let ptr = if len == 0 && mem::size_of::<T>() != 0 {
+            if !ptr.is_null() {
+                let _ = boot_services.free_pool(ptr as *mut u8);
+            }
+            NonNull::dangling().as_ptr()
+        } else {
+            ptr
+        };
+        let ptr = ptr::slice_from_raw_parts_mut(ptr, len);
+        Self::from_raw_pool(ptr, boot_services)
+    }
+
+    /// Allocate pool memory of *memory_type* and clone the elements of *values* into it.
+    pub fn new_slice_from(values: &[T], memory_type: MemoryType, boot_services: &'a B) -> Result<Self, efi::Status>
+    where
+        T: Clone,
+    {
+        let layout = Layout::array::<T>(values.len()).map_err(|_| efi::Status::INVALID_PARAMETER)?;
+        let ptr = allocate(layout, memory_type, boot_services)? as *mut T;
+        for (i, value) in values.iter().enumerate() {
+            unsafe { ptr.add(i).write(value.clone()) };
+        }
+        let ptr = ptr::slice_from_raw_parts_mut(ptr, values.len());
+        Ok(Self { ptr, memory_type, adopted: false, boot_services })
     }
 }
 
+fn allocate<B: BootServices>(
+    layout: Layout,
+    memory_type: MemoryType,
+    boot_services: &B,
+) -> Result<*mut u8, efi::Status> {
+    Ok(BootServicesAllocator::new(boot_services, memory_type).allocate(layout)?.as_ptr() as *mut u8)
+}
+
 impl<T: ?Sized, B: BootServices> Drop for BootServicesBox<'_, T, B> {
     fn drop(&mut self) {
-        let _ = self.boot_services.free_pool(self.ptr as *mut u8);
+        //SAFETY: The box owns a valid `T` allocated as described by `adopted`.
+        unsafe {
+            let layout = Layout::for_value(&*self.ptr);
+            ptr::drop_in_place(self.ptr);
+            let Some(ptr) = NonNull::new(self.ptr as *mut u8) else {
+                return;
+            };
+            if !self.adopted {
+                let _ = BootServicesAllocator::new(self.boot_services, self.memory_type).deallocate(ptr, layout);
+            } else if layout.size() != 0 {
+                // Adopted pool buffers have no alignment tracker, empty ones are dangling (see `from_raw_parts`).
+                let _ = self.boot_services.free_pool(ptr.as_ptr());
+            }
+        }
     }
 }
 
@@ -78,3 +215,297 @@ impl<T: ?Sized, B: BootServices> AsMut<T> for BootServicesBox<'_, T, B> {
         self.deref_mut()
     }
 }
+
+impl<T: Clone, B: BootServices> Clone for BootServicesBox<'_, T, B> {
+    fn clone(&self) -> Self {
+        Self::new(self.deref().clone(), self.memory_type, self.boot_services)
+    }
+}
+
+impl<T: Clone, B: BootServices> Clone for BootServicesBox<'_, [T], B> {
+    fn clone(&self) -> Self {
+        Self::new_slice_from(self, self.memory_type, self.boot_services).unwrap()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, B: BootServices> fmt::Debug for BootServicesBox<'_, T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.deref(), f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, B: BootServices> fmt::Display for BootServicesBox<'_, T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.deref(), f)
+    }
+}
+
+/// Value of a box given up as a [`StaticPtr`], stored with what is needed to free it in [`StaticPtr::drop_raw`].
+#[repr(C)]
+struct StaticBoxed<T, B: BootServices + 'static> {
+    boot_services: &'static B,
+    memory_type: MemoryType,
+    value: T,
+}
+
+impl<T, B: BootServices> BootServicesBox<'static, T, B> {
+    /// Move the value in a pool allocation of the same memory type that also records the boot services and return a
+    /// pointer to the value.
+    fn into_static_raw(self) -> *mut T {
+        let this = mem::ManuallyDrop::new(self);
+        let (memory_type, boot_services) = (this.memory_type, this.boot_services);
+        //SAFETY: The value is moved out of the box which is then freed without dropping its content.
+        let value = unsafe { ptr::read(this.ptr) };
+        drop(BootServicesBox {
+            ptr: this.ptr as *mut MaybeUninit<T>,
+            memory_type,
+            adopted: this.adopted,
+            boot_services,
+        });
+        let boxed = BootServicesBox::new(StaticBoxed { boot_services, memory_type, value }, memory_type, boot_services);
+        //SAFETY: The pointer comes from a box that has just been released.
+        unsafe { ptr::addr_of_mut!((*boxed.into_raw()).value) }
+    }
+}
+
+/// The value is moved to a new pool allocation of the same memory type that also records the boot services, so it
+/// can be freed by [`StaticPtr::drop_raw`]. [`StaticPtr::into_raw`] panics if this allocation fails.
+unsafe impl<T, B> StaticPtr for BootServicesBox<'static, T, B>
+where
+    T: Sized + 'static,
+    B: BootServices,
+{
+    type Pointee = T;
+
+    fn into_raw(self) -> *const Self::Pointee {
+        self.into_static_raw()
+    }
+
+    unsafe fn drop_raw(ptr: *const Self::Pointee) {
+        let boxed = (ptr as *mut u8).sub(mem::offset_of!(StaticBoxed<T, B>, value)) as *mut StaticBoxed<T, B>;
+        drop(BootServicesBox::from_raw(boxed, (*boxed).memory_type, (*boxed).boot_services));
+    }
+}
+
+unsafe impl<T, B> StaticPtrMut for BootServicesBox<'static, T, B>
+where
+    T: Sized + 'static,
+    B: BootServices,
+{
+    fn into_raw_mut(self) -> *mut Self::Pointee {
+        self.into_static_raw()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec};
+    use core::cell::Cell;
+    use mockall::predicate::eq;
+    use std::{
+        alloc::{GlobalAlloc, System},
+        sync::{Arc, Mutex},
+    };
+
+    /// Leaked boot services accepting any call to free_pool, for boxes of buffers that are not allocated from pool.
+    pub(crate) fn free_pool_boot_services() -> &'static MockBootServices {
+        let boot_services = Box::leak(Box::new(MockBootServices::new()));
+        boot_services.expect_free_pool().returning(|_| Ok(()));
+        boot_services
+    }
+
+    /// Box *data* as if it was a buffer allocated from pool and returned by the firmware.
+    pub(crate) fn boxed_slice<T: 'static>(data: Vec<T>) -> BootServicesBox<'static, [T], MockBootServices> {
+        let len = data.len();
+        //SAFETY: The buffer is leaked and freeing it is a no-op.
+        unsafe {
+            BootServicesBox::from_raw_parts(
+                Box::leak(data.into_boxed_slice()).as_mut_ptr(),
+                len,
+                free_pool_boot_services(),
+            )
+        }
+    }
+
+    /// Boot services allocating pools of *memory_type* from the system allocator and recording the outstanding
+    /// allocations.
+    pub(crate) fn boot_services(memory_type: MemoryType, allocations: Arc<Mutex<Vec<usize>>>) -> MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        let allocated = allocations.clone();
+        boot_services.expect_allocate_pool().with(eq(memory_type), mockall::predicate::always()).returning(
+            move |_, size| {
+                let ptr = unsafe { System.alloc(Layout::from_size_align(size.max(1), 8).unwrap()) };
+                allocated.lock().unwrap().push(ptr as usize);
+                Ok(ptr)
+            },
+        );
+        boot_services.expect_free_pool().returning(move |ptr| {
+            let mut allocations = allocations.lock().unwrap();
+            let index = allocations.iter().position(|p| *p == ptr as usize).expect("Freeing unknown pool.");
+            allocations.swap_remove(index);
+            Ok(())
+        });
+        boot_services
+    }
+
+    struct DropCounter(Rc<Cell<usize>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_content_is_dropped_before_free() {
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::LOADER_DATA, allocations.clone());
+        let drop_count = Rc::new(Cell::new(0));
+
+        let boxed = BootServicesBox::new(DropCounter(drop_count.clone()), MemoryType::LOADER_DATA, &boot_services);
+        assert_eq!(MemoryType::LOADER_DATA, boxed.memory_type());
+        assert_eq!(1, allocations.lock().unwrap().len());
+        drop(boxed);
+        assert_eq!(1, drop_count.get());
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_try_new_failure() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pool().returning(|_, _| Err(efi::Status::OUT_OF_RESOURCES));
+        boot_services.expect_free_pool().never();
+
+        let result = BootServicesBox::try_new(1_u64, MemoryType::BOOT_SERVICES_DATA, &boot_services);
+        assert_eq!(efi::Status::OUT_OF_RESOURCES, result.unwrap_err());
+    }
+
+    #[test]
+    fn test_new_zeroed_and_uninit() {
+        #[repr(align(32))]
+        #[derive(Debug, PartialEq)]
+        struct Aligned([u64; 4]);
+
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::BOOT_SERVICES_DATA, allocations.clone());
+
+        let zeroed = BootServicesBox::<Aligned, _>::new_zeroed(MemoryType::BOOT_SERVICES_DATA, &boot_services).unwrap();
+        let zeroed = unsafe { zeroed.assume_init() };
+        assert_eq!(0, &*zeroed as *const Aligned as usize % 32);
+        assert_eq!(Aligned([0; 4]), *zeroed);
+
+        let uninit = BootServicesBox::<Aligned, _>::new_uninit(MemoryType::BOOT_SERVICES_DATA, &boot_services).unwrap();
+        let value = uninit.write(Aligned([1, 2, 3, 4]));
+        assert_eq!("Aligned([1, 2, 3, 4])", format!("{value:?}"));
+
+        drop((zeroed, value));
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slice_from_and_clone() {
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::RUNTIME_SERVICES_DATA, allocations.clone());
+
+        let values = [String::from("a"), String::from("b")];
+        let boxed =
+            BootServicesBox::new_slice_from(&values, MemoryType::RUNTIME_SERVICES_DATA, &boot_services).unwrap();
+        let cloned = boxed.clone();
+        assert_eq!(values, *cloned);
+        assert_eq!(MemoryType::RUNTIME_SERVICES_DATA, cloned.memory_type());
+        assert_ne!(boxed.as_ptr(), cloned.as_ptr());
+        assert_eq!(2, allocations.lock().unwrap().len());
+
+        let string = BootServicesBox::new(String::from("text"), MemoryType::RUNTIME_SERVICES_DATA, &boot_services);
+        assert_eq!("text", format!("{}", string.clone()));
+
+        drop((boxed, cloned, string));
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_into_raw_from_raw() {
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::BOOT_SERVICES_DATA, allocations.clone());
+
+        let boxed =
+            BootServicesBox::new_slice_from(&[1_u32, 2, 3], MemoryType::BOOT_SERVICES_DATA, &boot_services).unwrap();
+        let ptr = boxed.into_raw();
+        assert_eq!(1, allocations.lock().unwrap().len());
+        let boxed = unsafe { BootServicesBox::from_raw(ptr, MemoryType::BOOT_SERVICES_DATA, &boot_services) };
+        assert_eq!([1, 2, 3], *boxed);
+        drop(boxed);
+        assert!(allocations.lock().unwrap().is_empty());
+
+        // Over-aligned boxes keep their alignment tracker through the round trip.
+        #[repr(align(32))]
+        struct Aligned(u64);
+        let boxed = BootServicesBox::new(Aligned(1), MemoryType::BOOT_SERVICES_DATA, &boot_services);
+        let ptr = boxed.into_raw();
+        let boxed = unsafe { BootServicesBox::from_raw(ptr, MemoryType::BOOT_SERVICES_DATA, &boot_services) };
+        assert_eq!(1, boxed.0);
+        drop(boxed);
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_raw_pool_frees_pool_directly() {
+        #[repr(align(32))]
+        struct Aligned(u64);
+
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::BOOT_SERVICES_DATA, allocations.clone());
+
+        // A plain pool buffer has no alignment tracker, it is given back as is to free_pool.
+        let ptr = unsafe { System.alloc(Layout::new::<Aligned>()) } as *mut Aligned;
+        allocations.lock().unwrap().push(ptr as usize);
+        unsafe { ptr.write(Aligned(7)) };
+        let boxed = unsafe { BootServicesBox::from_raw_pool(ptr, &boot_services) };
+        assert_eq!(7, boxed.0);
+        assert_eq!(MemoryType::BOOT_SERVICES_DATA, boxed.memory_type());
+        drop(boxed);
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_static_ptr() {
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services: &'static MockBootServices =
+            Box::leak(Box::new(boot_services(MemoryType::RUNTIME_SERVICES_DATA, allocations.clone())));
+        let drop_count = Rc::new(Cell::new(0));
+
+        let boxed =
+            BootServicesBox::new(DropCounter(drop_count.clone()), MemoryType::RUNTIME_SERVICES_DATA, boot_services);
+        let ptr = StaticPtr::into_raw(boxed);
+        assert_eq!(0, drop_count.get());
+        assert_eq!(1, allocations.lock().unwrap().len());
+        assert!(Rc::ptr_eq(&drop_count, unsafe { &(*ptr).0 }));
+
+        unsafe { <BootServicesBox<'static, DropCounter, MockBootServices> as StaticPtr>::drop_raw(ptr) };
+        assert_eq!(1, drop_count.get());
+        assert!(allocations.lock().unwrap().is_empty());
+
+        let boxed = Some(BootServicesBox::new(
+            DropCounter(drop_count.clone()),
+            MemoryType::RUNTIME_SERVICES_DATA,
+            boot_services,
+        ));
+        let ptr = StaticPtrMut::into_raw_mut(boxed);
+        unsafe { <Option<BootServicesBox<'static, DropCounter, MockBootServices>> as StaticPtr>::drop_raw(ptr) };
+        assert_eq!(2, drop_count.get());
+        assert!(allocations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_empty_slice_from_raw_parts_is_freed() {
+        let allocations = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(MemoryType::BOOT_SERVICES_DATA, allocations.clone());
+
+        let ptr = boot_services.allocate_pool(MemoryType::BOOT_SERVICES_DATA, 0).unwrap();
+        let boxed = unsafe { BootServicesBox::from_raw_parts(ptr as *mut u64, 0, &boot_services) };
+        assert!(allocations.lock().unwrap().is_empty());
+        assert!(boxed.is_empty());
+    }
+}
@@ -0,0 +1,715 @@
+//! UEFI Driver Model support, implementing the driver binding protocol on top of a [`Driver`].
+//!
+//! ```ignore
+//! struct MyDriver;
+//!
+//! impl Driver for MyDriver {
+//!     type ControllerData = MyControllerData;
+//!
+//!     fn supported<B: BootServices>(&self, context: &mut DriverContext<'_, B>, _: Option<&DevicePath>) -> Result<(), efi::Status> {
+//!         context.open_protocol(&PciIo, OpenProtocolAttributes::BY_DRIVER).map(|_| ())
+//!     }
+//!     // ...
+//! }
+//!
+//! DriverInstance::install(boot_services, image_handle, None, MyDriver)?;
+//! ```
+//!
+//! [UEFI Spec Documentation: 11.1. EFI Driver Binding Protocol](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-driver-binding-protocol)
+
+use alloc::vec::Vec;
+use core::{cell::RefCell, ffi::c_void, fmt, mem, ptr::NonNull, slice};
+
+use r_efi::efi;
+
+use crate::{
+    allocation::MemoryType,
+    boxed::BootServicesBox,
+    handle::Handle,
+    protocol_handler::{DriverBinding, OpenProtocolAttributes, Protocol},
+    BootServices,
+};
+
+type DevicePath = efi::protocols::device_path::Protocol;
+
+/// Driver following the UEFI Driver Model, see [`DriverInstance::install`].
+///
+/// Protocols opened on the controller with the [`DriverContext`] by driver, exclusively or by a child controller are
+/// closed automatically: at the end of [`Driver::supported`], when [`Driver::start`] or [`Driver::start_again`] fails,
+/// and after [`Driver::stop`] succeeds.
+pub trait Driver: Sized + 'static {
+    /// Private data of a controller managed by the driver, created by [`Driver::start`] and dropped once the
+    /// controller is stopped.
+    type ControllerData: 'static;
+
+    /// Version of the driver, drivers with higher versions are started first.
+    const VERSION: u32 = 0x10;
+
+    /// Test if the driver supports the controller of *context*.
+    ///
+    /// [UEFI Spec Documentation: 11.1.1. EFI_DRIVER_BINDING_PROTOCOL.Supported()](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-driver-binding-protocol-supported)
+    fn supported<B: BootServices>(
+        &self,
+        context: &mut DriverContext<'_, B>,
+        remaining_device_path: Option<&DevicePath>,
+    ) -> Result<(), efi::Status>;
+
+    /// Start managing the controller of *context*.
+    ///
+    /// [UEFI Spec Documentation: 11.1.2. EFI_DRIVER_BINDING_PROTOCOL.Start()](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-driver-binding-protocol-start)
+    fn start<B: BootServices>(
+        &self,
+        context: &mut DriverContext<'_, B>,
+        remaining_device_path: Option<&DevicePath>,
+    ) -> Result<Self::ControllerData, efi::Status>;
+
+    /// Called instead of [`Driver::start`] when the controller of *context* is already managed by the driver, e.g. for
+    /// a bus driver to create the child described by *remaining_device_path*.
+    ///
+    /// By default [`efi::Status::ALREADY_STARTED`] is returned.
+    fn start_again<B: BootServices>(
+        &self,
+        _context: &mut DriverContext<'_, B>,
+        _controller_data: &mut Self::ControllerData,
+        _remaining_device_path: Option<&DevicePath>,
+    ) -> Result<(), efi::Status> {
+        Err(efi::Status::ALREADY_STARTED)
+    }
+
+    /// Stop managing the controller of *context* when *children* is empty, otherwise destroy the *children*
+    /// handles of a bus driver.
+    ///
+    /// [UEFI Spec Documentation: 11.1.3. EFI_DRIVER_BINDING_PROTOCOL.Stop()](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-driver-binding-protocol-stop)
+    fn stop<B: BootServices>(
+        &self,
+        context: &mut DriverContext<'_, B>,
+        controller_data: &mut Self::ControllerData,
+        children: &[Handle],
+    ) -> Result<(), efi::Status>;
+}
+
+/// Controller a [`Driver`] is called for, keeping track of the protocols opened on it.
+pub struct DriverContext<'a, B: BootServices> {
+    boot_services: &'a B,
+    driver_binding_handle: Handle,
+    controller: Handle,
+    opened_protocols: Vec<OpenedProtocolRecord>,
+}
+
+/// Protocol opened on the controller on behalf of *controller_handle*.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OpenedProtocolRecord {
+    protocol: &'static efi::Guid,
+    controller_handle: Handle,
+}
+
+impl<'a, B: BootServices> DriverContext<'a, B> {
+    /// Boot services used by the driver binding protocol.
+    pub fn boot_services(&self) -> &'a B {
+        self.boot_services
+    }
+
+    /// Handle on which the driver binding protocol is installed, used as agent handle.
+    pub fn driver_binding_handle(&self) -> Handle {
+        self.driver_binding_handle
+    }
+
+    /// Handle of the controller the driver is called for.
+    pub fn controller(&self) -> Handle {
+        self.controller
+    }
+
+    /// Open *protocol* on the controller with *attribute*.
+    ///
+    /// Protocols opened with [`OpenProtocolAttributes::BY_DRIVER`] or [`OpenProtocolAttributes::EXCLUSIVE`] are
+    /// tracked to be closed automatically. Other opens are not tracked: closing them would also close the opens of
+    /// the driver on the same controller, e.g. when [`Driver::supported`] is called on a controller already started.
+    ///
+    /// See [`BootServices::open_protocol`].
+    pub fn open_protocol<P: Protocol + 'static>(
+        &mut self,
+        protocol: &P,
+        attribute: OpenProtocolAttributes,
+    ) -> Result<&'static mut P::Interface, efi::Status> {
+        self.open_protocol_for(protocol, self.controller, attribute)
+    }
+
+    /// Open *protocol* on the controller by the *child* handle created by a bus driver, the protocol is tracked to
+    /// be closed automatically when *child* is stopped.
+    pub fn open_protocol_by_child<P: Protocol + 'static>(
+        &mut self,
+        protocol: &P,
+        child: Handle,
+    ) -> Result<&'static mut P::Interface, efi::Status> {
+        self.open_protocol_for(protocol, child, OpenProtocolAttributes::BY_CHILD_CONTROLLER)
+    }
+
+    /// Protocols currently tracked, with the handle of the controller that requires them.
+    pub fn opened_protocols(&self) -> impl Iterator<Item = (&'static efi::Guid, Handle)> + '_ {
+        self.opened_protocols.iter().map(|record| (record.protocol, record.controller_handle))
+    }
+
+    fn open_protocol_for<P: Protocol + 'static>(
+        &mut self,
+        protocol: &P,
+        controller_handle: Handle,
+        attribute: OpenProtocolAttributes,
+    ) -> Result<&'static mut P::Interface, efi::Status> {
+        let record = OpenedProtocolRecord { protocol: protocol.protocol_guid(), controller_handle };
+        //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
+        let interface = unsafe {
+            self.boot_services.open_protocol_unchecked(
+                self.controller.as_ptr(),
+                record.protocol,
+                self.driver_binding_handle.as_ptr(),
+                controller_handle.as_ptr(),
+                attribute,
+            )?
+        };
+        let tracked = [
+            OpenProtocolAttributes::BY_DRIVER,
+            OpenProtocolAttributes::EXCLUSIVE,
+            OpenProtocolAttributes::BY_CHILD_CONTROLLER,
+        ]
+        .into_iter()
+        .any(|tracked| attribute.contains(tracked));
+        if tracked && !self.opened_protocols.contains(&record) {
+            self.opened_protocols.push(record);
+        }
+        match NonNull::new(interface as *mut P::Interface) {
+            //SAFETY: The interface stays valid until the protocol is closed.
+            Some(mut interface) => Ok(unsafe { interface.as_mut() }),
+            // Protocols without interface (e.g. `()`) are installed with a null pointer.
+            None if mem::size_of::<P::Interface>() == 0 => Ok(unsafe { NonNull::<P::Interface>::dangling().as_mut() }),
+            None => Err(efi::Status::UNSUPPORTED),
+        }
+    }
+
+    /// Close the tracked protocols matching *filter*.
+    fn close_protocols(&mut self, filter: impl Fn(&OpenedProtocolRecord) -> bool) {
+        let (boot_services, controller, driver_binding_handle) =
+            (self.boot_services, self.controller, self.driver_binding_handle);
+        self.opened_protocols.retain(|record| {
+            if !filter(record) {
+                return true;
+            }
+            let _ = boot_services.close_protocol(
+                controller.as_ptr(),
+                record.protocol,
+                driver_binding_handle.as_ptr(),
+                record.controller_handle.as_ptr(),
+            );
+            false
+        });
+    }
+}
+
+impl<B: BootServices> fmt::Debug for DriverContext<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DriverContext")
+            .field("driver_binding_handle", &self.driver_binding_handle)
+            .field("controller", &self.controller)
+            .field("opened_protocols", &self.opened_protocols)
+            .finish()
+    }
+}
+
+struct ControllerRecord<T> {
+    controller: Handle,
+    data: T,
+    opened_protocols: Vec<OpenedProtocolRecord>,
+}
+
+/// Driver binding protocol installed for a [`Driver`].
+///
+/// The driver binding protocol interface is the first field, the instance is found back from the interface pointer
+/// given to the trampoline functions.
+#[repr(C)]
+pub struct DriverInstance<D: Driver, B: BootServices + 'static> {
+    protocol: efi::protocols::driver_binding::Protocol,
+    boot_services: &'static B,
+    driver: D,
+    controllers: RefCell<Vec<ControllerRecord<D::ControllerData>>>,
+}
+
+impl<D: Driver, B: BootServices + 'static> DriverInstance<D, B> {
+    /// Install the driver binding protocol of *driver* on *driver_binding_handle*, or on *image_handle* if `None`.
+    ///
+    /// The instance is allocated from pool and stays installed for the lifetime of the image.
+    ///
+    /// [UEFI Spec Documentation: 7.3.2. EFI_BOOT_SERVICES.InstallProtocolInterface()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-installprotocolinterface)
+    pub fn install(
+        boot_services: &'static B,
+        image_handle: Handle,
+        driver_binding_handle: Option<Handle>,
+        driver: D,
+    ) -> Result<&'static Self, efi::Status> {
+        let driver_binding_handle = driver_binding_handle.unwrap_or(image_handle);
+        let instance = Self {
+            protocol: efi::protocols::driver_binding::Protocol {
+                supported: Self::efi_supported,
+                start: Self::efi_start,
+                stop: Self::efi_stop,
+                version: D::VERSION,
+                image_handle: image_handle.as_ptr(),
+                driver_binding_handle: driver_binding_handle.as_ptr(),
+            },
+            boot_services,
+            driver,
+            controllers: RefCell::new(Vec::new()),
+        };
+        let instance = BootServicesBox::try_new(instance, MemoryType::BOOT_SERVICES_DATA, boot_services)?.into_raw();
+        //SAFETY: The driver binding protocol interface is the first field of the repr(C) instance.
+        let status = unsafe {
+            boot_services.install_protocol_interface_unchecked(
+                Some(driver_binding_handle.as_ptr()),
+                DriverBinding.protocol_guid(),
+                instance as *mut c_void,
+            )
+        };
+        match status {
+            //SAFETY: The instance is never freed once installed.
+            Ok(_) => Ok(unsafe { &*instance }),
+            Err(status) => {
+                //SAFETY: The instance has not been installed.
+                drop(unsafe { BootServicesBox::from_raw(instance, MemoryType::BOOT_SERVICES_DATA, boot_services) });
+                Err(status)
+            }
+        }
+    }
+
+    /// Driver called by the driver binding protocol.
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    /// Handle of the image that produced the driver binding protocol.
+    pub fn image_handle(&self) -> Handle {
+        Handle::new(self.protocol.image_handle)
+    }
+
+    /// Handle on which the driver binding protocol is installed, used as agent handle to open protocols.
+    pub fn driver_binding_handle(&self) -> Handle {
+        Handle::new(self.protocol.driver_binding_handle)
+    }
+
+    /// Controllers currently managed by the driver.
+    pub fn controllers(&self) -> Vec<Handle> {
+        self.controllers.borrow().iter().map(|record| record.controller).collect()
+    }
+
+    /// Call *f* with the private data of *controller*, `None` if the controller is not managed by the driver or if
+    /// its private data is already in use.
+    pub fn with_controller_data<R>(
+        &self,
+        controller: Handle,
+        f: impl FnOnce(&mut D::ControllerData) -> R,
+    ) -> Option<R> {
+        let mut controllers = self.controllers.try_borrow_mut().ok()?;
+        let record = controllers.iter_mut().find(|record| record.controller == controller)?;
+        Some(f(&mut record.data))
+    }
+
+    /// Put back the record of a controller taken out while the driver is called.
+    ///
+    /// The controllers can only be borrowed at this point if the protocol function is called from a
+    /// [`Self::with_controller_data`] closure, which is refused on entry. If it happens anyway, the protocols of the
+    /// controller are closed and its private data dropped.
+    fn put_back(&self, mut record: ControllerRecord<D::ControllerData>) -> Result<(), efi::Status> {
+        match self.controllers.try_borrow_mut() {
+            Ok(mut controllers) => {
+                controllers.push(record);
+                Ok(())
+            }
+            Err(_) => {
+                self.context(record.controller, mem::take(&mut record.opened_protocols)).close_protocols(|_| true);
+                Err(efi::Status::ACCESS_DENIED)
+            }
+        }
+    }
+
+    fn context(&self, controller: Handle, opened_protocols: Vec<OpenedProtocolRecord>) -> DriverContext<'static, B> {
+        DriverContext {
+            boot_services: self.boot_services,
+            driver_binding_handle: self.driver_binding_handle(),
+            controller,
+            opened_protocols,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// *this* must be the driver binding protocol interface of an instance of `Self`.
+    unsafe fn from_protocol<'b>(this: *mut efi::protocols::driver_binding::Protocol) -> &'b Self {
+        &*(this as *const Self)
+    }
+
+    extern "efiapi" fn efi_supported(
+        this: *mut efi::protocols::driver_binding::Protocol,
+        controller: efi::Handle,
+        remaining_device_path: *mut DevicePath,
+    ) -> efi::Status {
+        //SAFETY: The driver binding protocol is only installed by Self::install.
+        let instance = unsafe { Self::from_protocol(this) };
+        let mut context = instance.context(Handle::new(controller), Vec::new());
+        let result = instance.driver.supported(&mut context, unsafe { remaining_device_path.as_ref() });
+        context.close_protocols(|_| true);
+        result.err().unwrap_or(efi::Status::SUCCESS)
+    }
+
+    extern "efiapi" fn efi_start(
+        this: *mut efi::protocols::driver_binding::Protocol,
+        controller: efi::Handle,
+        remaining_device_path: *mut DevicePath,
+    ) -> efi::Status {
+        //SAFETY: The driver binding protocol is only installed by Self::install.
+        let instance = unsafe { Self::from_protocol(this) };
+        let controller = Handle::new(controller);
+        //SAFETY: The remaining device path is null or a valid device path given by the firmware.
+        let remaining_device_path = unsafe { remaining_device_path.as_ref() };
+
+        // The record is taken out while the driver is called, the driver may reenter the driver binding protocol.
+        let Ok(mut controllers) = instance.controllers.try_borrow_mut() else {
+            return efi::Status::ACCESS_DENIED;
+        };
+        let record = controllers
+            .iter()
+            .position(|record| record.controller == controller)
+            .map(|index| controllers.swap_remove(index));
+        drop(controllers);
+
+        let mut context = instance.context(controller, Vec::new());
+        let (mut record, result) = match record {
+            None => match instance.driver.start(&mut context, remaining_device_path) {
+                Ok(data) => (ControllerRecord { controller, data, opened_protocols: Vec::new() }, Ok(())),
+                Err(status) => {
+                    context.close_protocols(|_| true);
+                    return status;
+                }
+            },
+            Some(mut record) => {
+                let result = instance.driver.start_again(&mut context, &mut record.data, remaining_device_path);
+                if result.is_err() {
+                    context.close_protocols(|_| true);
+                }
+                (record, result)
+            }
+        };
+        record.opened_protocols.append(&mut context.opened_protocols);
+        result.and(instance.put_back(record)).err().unwrap_or(efi::Status::SUCCESS)
+    }
+
+    extern "efiapi" fn efi_stop(
+        this: *mut efi::protocols::driver_binding::Protocol,
+        controller: efi::Handle,
+        number_of_children: usize,
+        child_handle_buffer: *mut efi::Handle,
+    ) -> efi::Status {
+        //SAFETY: The driver binding protocol is only installed by Self::install.
+        let instance = unsafe { Self::from_protocol(this) };
+        let controller = Handle::new(controller);
+        let children = match number_of_children {
+            0 => &[][..],
+            //SAFETY: Handle is repr(transparent) over efi::Handle and the buffer holds number_of_children handles.
+            n => unsafe { slice::from_raw_parts(child_handle_buffer as *const Handle, n) },
+        };
+
+        // The record is taken out while the driver is called, the driver may reenter the driver binding protocol.
+        let Ok(mut controllers) = instance.controllers.try_borrow_mut() else {
+            return efi::Status::ACCESS_DENIED;
+        };
+        let Some(index) = controllers.iter().position(|record| record.controller == controller) else {
+            return efi::Status::NOT_STARTED;
+        };
+        let mut record = controllers.swap_remove(index);
+        drop(controllers);
+
+        let mut context = instance.context(controller, mem::take(&mut record.opened_protocols));
+        let result = instance.driver.stop(&mut context, &mut record.data, children);
+        match result {
+            Ok(()) if children.is_empty() => {
+                context.close_protocols(|_| true);
+                return efi::Status::SUCCESS;
+            }
+            Ok(()) => context.close_protocols(|record| children.contains(&record.controller_handle)),
+            Err(_) => (),
+        }
+        record.opened_protocols = mem::take(&mut context.opened_protocols);
+        result.and(instance.put_back(record)).err().unwrap_or(efi::Status::SUCCESS)
+    }
+}
+
+impl<D: Driver + fmt::Debug, B: BootServices + 'static> fmt::Debug for DriverInstance<D, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DriverInstance")
+            .field("driver", &self.driver)
+            .field("version", &self.protocol.version)
+            .field("image_handle", &self.image_handle())
+            .field("driver_binding_handle", &self.driver_binding_handle())
+            .field("controllers", &self.controllers())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        protocol_handler::{BlockIo, DevicePath as DevicePathProtocol},
+        MockBootServices,
+    };
+    use alloc::{boxed::Box, vec};
+    use core::{alloc::Layout, ptr};
+    use std::{
+        alloc::{GlobalAlloc, System},
+        sync::{Arc, Mutex},
+    };
+
+    const IMAGE_HANDLE: usize = 1;
+    const CHILD_HANDLE: usize = 0x100;
+    const FAILING_CONTROLLER: usize = 0xBAD;
+
+    /// Bus driver creating a child for each controller, failing to start *FAILING_CONTROLLER*.
+    #[derive(Debug)]
+    struct TestDriver;
+
+    impl Driver for TestDriver {
+        type ControllerData = Vec<Handle>;
+
+        const VERSION: u32 = 0x20;
+
+        fn supported<B: BootServices>(
+            &self,
+            context: &mut DriverContext<'_, B>,
+            _remaining_device_path: Option<&DevicePath>,
+        ) -> Result<(), efi::Status> {
+            // Opens that are not by driver are left open, they would close the opens of a started controller.
+            context.open_protocol(&DevicePathProtocol, OpenProtocolAttributes::GET_PROTOCOL)?;
+            context.open_protocol(&BlockIo, OpenProtocolAttributes::BY_DRIVER).map(|_| ())
+        }
+
+        fn start<B: BootServices>(
+            &self,
+            context: &mut DriverContext<'_, B>,
+            _remaining_device_path: Option<&DevicePath>,
+        ) -> Result<Self::ControllerData, efi::Status> {
+            context.open_protocol(&BlockIo, OpenProtocolAttributes::BY_DRIVER)?;
+            context.open_protocol(&DevicePathProtocol, OpenProtocolAttributes::BY_DRIVER)?;
+            if context.controller().as_ptr() as usize == FAILING_CONTROLLER {
+                return Err(efi::Status::DEVICE_ERROR);
+            }
+            let child = Handle::new(CHILD_HANDLE as efi::Handle);
+            context.open_protocol_by_child(&BlockIo, child)?;
+            Ok(vec![child])
+        }
+
+        fn start_again<B: BootServices>(
+            &self,
+            context: &mut DriverContext<'_, B>,
+            controller_data: &mut Self::ControllerData,
+            remaining_device_path: Option<&DevicePath>,
+        ) -> Result<(), efi::Status> {
+            if remaining_device_path.is_none() {
+                return Err(efi::Status::ALREADY_STARTED);
+            }
+            let child = Handle::new((CHILD_HANDLE + controller_data.len()) as efi::Handle);
+            context.open_protocol_by_child(&BlockIo, child)?;
+            controller_data.push(child);
+            Ok(())
+        }
+
+        fn stop<B: BootServices>(
+            &self,
+            _context: &mut DriverContext<'_, B>,
+            controller_data: &mut Self::ControllerData,
+            children: &[Handle],
+        ) -> Result<(), efi::Status> {
+            controller_data.retain(|child| !children.contains(child));
+            match children.is_empty() && !controller_data.is_empty() {
+                true => Err(efi::Status::ACCESS_DENIED),
+                false => Ok(()),
+            }
+        }
+    }
+
+    /// Boot services recording the protocols closed as (controller, protocol, controller handle) tuples.
+    fn boot_services(closed: Arc<Mutex<Vec<(usize, efi::Guid, usize)>>>) -> &'static MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_allocate_pool()
+            .returning(|_, size| Ok(unsafe { System.alloc(Layout::from_size_align(size, 8).unwrap()) }));
+        boot_services
+            .expect_install_protocol_interface_unchecked()
+            .withf(|handle, protocol, _| {
+                *handle == Some(IMAGE_HANDLE as efi::Handle)
+                    && *protocol == efi::protocols::driver_binding::PROTOCOL_GUID
+            })
+            .returning(|handle, _, _| Ok(handle.unwrap()));
+        boot_services
+            .expect_open_protocol_unchecked()
+            .withf(|_, _, agent_handle, _, _| *agent_handle as usize == IMAGE_HANDLE)
+            .returning(|_, _, _, _, _| Ok(0x10 as *mut c_void));
+        boot_services.expect_close_protocol().returning(move |handle, protocol, agent_handle, controller_handle| {
+            assert_eq!(IMAGE_HANDLE, agent_handle as usize);
+            closed.lock().unwrap().push((handle as usize, *protocol, controller_handle as usize));
+            Ok(())
+        });
+        Box::leak(Box::new(boot_services))
+    }
+
+    fn interface<D: Driver, B: BootServices>(
+        instance: &DriverInstance<D, B>,
+    ) -> *mut efi::protocols::driver_binding::Protocol {
+        instance as *const _ as *mut _
+    }
+
+    #[test]
+    fn test_driver_supported() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(closed.clone());
+
+        let instance =
+            DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver).unwrap();
+        assert_eq!(0x20, instance.protocol.version);
+        assert_eq!(IMAGE_HANDLE, instance.image_handle().as_ptr() as usize);
+        assert_eq!(IMAGE_HANDLE, instance.driver_binding_handle().as_ptr() as usize);
+
+        let status = (instance.protocol.supported)(interface(instance), 2 as efi::Handle, ptr::null_mut());
+        assert_eq!(efi::Status::SUCCESS, status);
+        // The protocols opened to test the controller are closed right away.
+        assert_eq!(vec![(2, efi::protocols::block_io::PROTOCOL_GUID, 2)], *closed.lock().unwrap());
+        assert!(instance.controllers().is_empty());
+    }
+
+    #[test]
+    fn test_driver_start_and_stop() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(closed.clone());
+        let instance =
+            DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver).unwrap();
+
+        let status = (instance.protocol.start)(interface(instance), 2 as efi::Handle, ptr::null_mut());
+        assert_eq!(efi::Status::SUCCESS, status);
+        assert!(closed.lock().unwrap().is_empty());
+        assert_eq!(vec![Handle::new(2 as efi::Handle)], instance.controllers());
+        assert_eq!(Some(1), instance.with_controller_data(Handle::new(2 as efi::Handle), |children| children.len()));
+        assert_eq!(
+            efi::Status::ALREADY_STARTED,
+            (instance.protocol.start)(interface(instance), 2 as efi::Handle, ptr::null_mut())
+        );
+
+        // The controller can not be stopped while it has children.
+        let status = (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 0, ptr::null_mut());
+        assert_eq!(efi::Status::ACCESS_DENIED, status);
+        assert!(closed.lock().unwrap().is_empty());
+
+        // Stopping the child closes the protocol opened by the child.
+        let mut children = [CHILD_HANDLE as efi::Handle];
+        let status = (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 1, children.as_mut_ptr());
+        assert_eq!(efi::Status::SUCCESS, status);
+        assert_eq!(vec![(2, efi::protocols::block_io::PROTOCOL_GUID, CHILD_HANDLE)], *closed.lock().unwrap());
+        closed.lock().unwrap().clear();
+
+        let status = (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 0, ptr::null_mut());
+        assert_eq!(efi::Status::SUCCESS, status);
+        assert_eq!(
+            vec![(2, efi::protocols::block_io::PROTOCOL_GUID, 2), (2, efi::protocols::device_path::PROTOCOL_GUID, 2)],
+            *closed.lock().unwrap()
+        );
+        assert!(instance.controllers().is_empty());
+        assert_eq!(
+            efi::Status::NOT_STARTED,
+            (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 0, ptr::null_mut())
+        );
+    }
+
+    #[test]
+    fn test_driver_start_again_creates_children() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(closed.clone());
+        let instance =
+            DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver).unwrap();
+        let mut remaining_device_path = efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        };
+
+        let status = (instance.protocol.start)(interface(instance), 2 as efi::Handle, ptr::null_mut());
+        assert_eq!(efi::Status::SUCCESS, status);
+        let status = (instance.protocol.start)(interface(instance), 2 as efi::Handle, &mut remaining_device_path);
+        assert_eq!(efi::Status::SUCCESS, status);
+        assert_eq!(vec![Handle::new(2 as efi::Handle)], instance.controllers());
+        assert_eq!(
+            Some(vec![Handle::new(CHILD_HANDLE as efi::Handle), Handle::new((CHILD_HANDLE + 1) as efi::Handle)]),
+            instance.with_controller_data(Handle::new(2 as efi::Handle), |children| children.clone())
+        );
+
+        // Both children are closed when they are stopped.
+        let mut children = [CHILD_HANDLE as efi::Handle, (CHILD_HANDLE + 1) as efi::Handle];
+        let status = (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 2, children.as_mut_ptr());
+        assert_eq!(efi::Status::SUCCESS, status);
+        assert_eq!(
+            vec![
+                (2, efi::protocols::block_io::PROTOCOL_GUID, CHILD_HANDLE),
+                (2, efi::protocols::block_io::PROTOCOL_GUID, CHILD_HANDLE + 1)
+            ],
+            *closed.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_driver_reentered_from_controller_data() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(closed.clone());
+        let instance =
+            DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver).unwrap();
+        let status = (instance.protocol.start)(interface(instance), 2 as efi::Handle, ptr::null_mut());
+        assert_eq!(efi::Status::SUCCESS, status);
+
+        // Connecting or disconnecting a controller while its private data is in use is denied instead of panicking.
+        let statuses = instance.with_controller_data(Handle::new(2 as efi::Handle), |_| {
+            (
+                (instance.protocol.start)(interface(instance), 3 as efi::Handle, ptr::null_mut()),
+                (instance.protocol.stop)(interface(instance), 2 as efi::Handle, 0, ptr::null_mut()),
+            )
+        });
+        assert_eq!(Some((efi::Status::ACCESS_DENIED, efi::Status::ACCESS_DENIED)), statuses);
+        assert_eq!(vec![Handle::new(2 as efi::Handle)], instance.controllers());
+    }
+
+    #[test]
+    fn test_driver_start_failure_closes_protocols() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let boot_services = boot_services(closed.clone());
+        let instance =
+            DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver).unwrap();
+
+        let status = (instance.protocol.start)(interface(instance), FAILING_CONTROLLER as efi::Handle, ptr::null_mut());
+        assert_eq!(efi::Status::DEVICE_ERROR, status);
+        assert_eq!(
+            vec![
+                (FAILING_CONTROLLER, efi::protocols::block_io::PROTOCOL_GUID, FAILING_CONTROLLER),
+                (FAILING_CONTROLLER, efi::protocols::device_path::PROTOCOL_GUID, FAILING_CONTROLLER)
+            ],
+            *closed.lock().unwrap()
+        );
+        assert!(instance.controllers().is_empty());
+    }
+
+    #[test]
+    fn test_driver_install_failure_frees_instance() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_allocate_pool()
+            .returning(|_, size| Ok(unsafe { System.alloc(Layout::from_size_align(size, 8).unwrap()) }));
+        boot_services
+            .expect_install_protocol_interface_unchecked()
+            .returning(|_, _, _| Err(efi::Status::INVALID_PARAMETER));
+        boot_services.expect_free_pool().times(1).returning(|_| Ok(()));
+        let boot_services = Box::leak(Box::new(boot_services));
+
+        let result = DriverInstance::install(boot_services, Handle::new(IMAGE_HANDLE as efi::Handle), None, TestDriver);
+        assert_eq!(efi::Status::INVALID_PARAMETER, result.unwrap_err());
+    }
+}
@@ -1,9 +1,19 @@
 //! This module defined every struct related to event in boot services.
 
-use core::ops;
+use alloc::boxed::Box;
+use core::{
+    cell::Cell,
+    ffi::c_void,
+    fmt,
+    mem::{self, ManuallyDrop},
+    ops, ptr,
+    time::Duration,
+};
 
 use r_efi::efi;
 
+use crate::{static_ptr::StaticPtr, tpl::Tpl, BootServices};
+
 /// Function signature for event notify function.
 pub type EventNotifyCallback<T> = extern "efiapi" fn(efi::Event, T);
 
@@ -38,6 +48,10 @@ impl Into<u32> for EventTimerType {
 pub struct EventType(u32);
 
 impl EventType {
+    /// The event has no special type, it is only signaled with [`BootServices::signal_event`](super::BootServices::signal_event)
+    /// and can be waited on with [`BootServices::wait_for_event`](super::BootServices::wait_for_event).
+    pub const NONE: EventType = EventType(0);
+
     /// The event is a timer event and may be passed to [`BootServices::set_timer`](super::BootServices::set_timer).
     /// Note that timers only function during boot services time.
     pub const TIMER: EventType = EventType(efi::EVT_TIMER);
@@ -67,6 +81,57 @@ impl EventType {
     /// The event is to be notified by the system when `SetVirtualAddressMap()` is performed.
     /// This event type is a composite of [`Self::NOTIFY_SIGNAL`], [`Self::RUNTIME`], and [`Self::RUNTIME`] and should not be combined with any other event types.
     pub const SIGNAL_VIRTUAL_ADDRESS_CHANGE: EventType = EventType(efi::EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE);
+
+    const ALL: EventType = EventType(
+        efi::EVT_TIMER
+            | efi::EVT_RUNTIME
+            | efi::EVT_NOTIFY_WAIT
+            | efi::EVT_NOTIFY_SIGNAL
+            | efi::EVT_SIGNAL_EXIT_BOOT_SERVICES
+            | efi::EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+    );
+
+    /// Return true if all the flags of *other* are set in self.
+    pub const fn contains(self, other: EventType) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Return true if any of the flags of *other* are set in self.
+    pub const fn intersects(self, other: EventType) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Check that the event type is a legal combination of flags for [`BootServices::create_event`].
+    ///
+    /// Return [`efi::Status::INVALID_PARAMETER`] if:
+    /// * an unknown flag is set.
+    /// * [`Self::NOTIFY_WAIT`] and [`Self::NOTIFY_SIGNAL`] are both set.
+    /// * [`Self::SIGNAL_EXIT_BOOT_SERVICES`] or [`Self::SIGNAL_VIRTUAL_ADDRESS_CHANGE`] is combined with another flag.
+    pub fn validate(self) -> Result<(), efi::Status> {
+        if !Self::ALL.contains(self) || self.contains(Self::NOTIFY_WAIT | Self::NOTIFY_SIGNAL) {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        // Both special types are composites, only their specific bits tell if they are used.
+        for special in [Self::SIGNAL_EXIT_BOOT_SERVICES, Self::SIGNAL_VIRTUAL_ADDRESS_CHANGE] {
+            let specific_bits = EventType(special.0 & !(Self::NOTIFY_SIGNAL | Self::RUNTIME).0);
+            if self.intersects(specific_bits) && self != special {
+                return Err(efi::Status::INVALID_PARAMETER);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the event type is a legal combination of flags for [`BootServices::create_event_ex`] with an event group.
+    ///
+    /// In addition to the checks of [`Self::validate`], [`Self::SIGNAL_EXIT_BOOT_SERVICES`] and
+    /// [`Self::SIGNAL_VIRTUAL_ADDRESS_CHANGE`] are not allowed, the equivalent [`EventGroup`] must be used instead.
+    pub fn validate_for_group(self) -> Result<(), efi::Status> {
+        self.validate()?;
+        if self == Self::SIGNAL_EXIT_BOOT_SERVICES || self == Self::SIGNAL_VIRTUAL_ADDRESS_CHANGE {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        Ok(())
+    }
 }
 
 impl ops::BitOr for EventType {
@@ -88,3 +153,819 @@ impl Into<u32> for EventType {
         self.0
     }
 }
+
+/// Well-known event groups defined by the UEFI specification.
+///
+/// See [`BootServices::create_event_ex`] and [`on_event_group`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventGroup {
+    /// Notified when `ExitBootServices()` is invoked, after the [`Self::BeforeExitBootServices`] group.
+    ExitBootServices,
+    /// Notified when `ExitBootServices()` is invoked, before the [`Self::ExitBootServices`] group.
+    BeforeExitBootServices,
+    /// Notified when `SetVirtualAddressMap()` is invoked.
+    VirtualAddressChange,
+    /// Notified when the memory map has changed.
+    MemoryMapChange,
+    /// Notified when the boot manager is about to load and execute a boot option.
+    ReadyToBoot,
+    /// Notified right after the [`Self::ReadyToBoot`] group.
+    AfterReadyToBoot,
+    /// Notified when `ResetSystem()` is invoked and the system is about to be reset.
+    ResetSystem,
+}
+
+impl EventGroup {
+    /// Return the guid of the event group.
+    pub const fn guid(self) -> &'static efi::Guid {
+        match self {
+            EventGroup::ExitBootServices => &efi::EVENT_GROUP_EXIT_BOOT_SERVICES,
+            EventGroup::BeforeExitBootServices => &efi::EVENT_GROUP_BEFORE_EXIT_BOOT_SERVICES,
+            EventGroup::VirtualAddressChange => &efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE,
+            EventGroup::MemoryMapChange => &efi::EVENT_GROUP_MEMORY_MAP_CHANGE,
+            EventGroup::ReadyToBoot => &efi::EVENT_GROUP_READY_TO_BOOT,
+            EventGroup::AfterReadyToBoot => &efi::EVENT_GROUP_AFTER_READY_TO_BOOT,
+            EventGroup::ResetSystem => &efi::EVENT_GROUP_RESET_SYSTEM,
+        }
+    }
+}
+
+impl From<EventGroup> for &'static efi::Guid {
+    fn from(event_group: EventGroup) -> Self {
+        event_group.guid()
+    }
+}
+
+/// RAII implementation of an event.
+///
+/// The event owns its notify context, which is dropped once the event is closed.
+/// The event is closed when this structure is dropped, see [`Event::close`] to get the status of the close.
+#[must_use]
+pub struct Event<'a, B: BootServices> {
+    boot_services: &'a B,
+    event: efi::Event,
+    notify_context: *const c_void,
+    drop_notify_context: Option<unsafe fn(*const c_void)>,
+}
+
+impl<'a, B: BootServices> Event<'a, B> {
+    /// Create an event that owns its notify context.
+    ///
+    /// The notify function only borrows the context through a pointer, the context itself is dropped once the event
+    /// is closed.
+    ///
+    /// See [`BootServices::create_event`] for more details.
+    pub fn new<T>(
+        boot_services: &'a B,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: Option<EventNotifyCallback<*mut T::Pointee>>,
+        notify_context: T,
+    ) -> Result<Self, efi::Status>
+    where
+        T: StaticPtr + 'static,
+    {
+        event_type.validate()?;
+        let notify_context = notify_context.into_raw();
+        //SAFETY: [`StaticPtr`] generic is used to guaranteed that the context stay valid until it is reclaimed on close.
+        let event = unsafe {
+            boot_services.create_event_unchecked(
+                event_type,
+                notify_tpl,
+                notify_function,
+                notify_context as *mut T::Pointee,
+            )
+        };
+        Self::from_create_result::<T>(boot_services, event, notify_context as *const c_void)
+    }
+
+    /// Create an event in a group that owns its notify context.
+    ///
+    /// Like [`Self::new`], the notify function only borrows the context through a pointer.
+    ///
+    /// See [`BootServices::create_event_ex`] for more details.
+    pub fn new_ex<T>(
+        boot_services: &'a B,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: EventNotifyCallback<*mut T::Pointee>,
+        notify_context: T,
+        event_group: &'static efi::Guid,
+    ) -> Result<Self, efi::Status>
+    where
+        T: StaticPtr + 'static,
+    {
+        event_type.validate_for_group()?;
+        let notify_context = notify_context.into_raw();
+        //SAFETY: [`StaticPtr`] generic is used to guaranteed that the context stay valid until it is reclaimed on close.
+        let event = unsafe {
+            boot_services.create_event_ex_unchecked(
+                event_type,
+                notify_tpl,
+                notify_function,
+                notify_context as *mut T::Pointee,
+                event_group,
+            )
+        };
+        Self::from_create_result::<T>(boot_services, event, notify_context as *const c_void)
+    }
+
+    /// Create an event whose notify function is a closure.
+    ///
+    /// The closure is boxed and called through a trampoline with a reference to the event being notified.
+    /// The box is freed when the event is closed.
+    ///
+    /// See [`BootServices::create_event`] for more details.
+    #[doc(alias = "create_event_with_closure")]
+    pub fn new_with_closure<F>(
+        boot_services: &'a B,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: F,
+    ) -> Result<Self, efi::Status>
+    where
+        F: FnMut(&Event<'a, B>) + 'static,
+    {
+        Self::new_with_closure_in_group(boot_services, event_type, notify_tpl, notify_function, None)
+    }
+
+    /// Create an event in a group whose notify function is a closure.
+    ///
+    /// See [`Self::new_with_closure`] and [`BootServices::create_event_ex`] for more details.
+    pub fn new_ex_with_closure<F>(
+        boot_services: &'a B,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: F,
+        event_group: &'static efi::Guid,
+    ) -> Result<Self, efi::Status>
+    where
+        F: FnMut(&Event<'a, B>) + 'static,
+    {
+        Self::new_with_closure_in_group(boot_services, event_type, notify_tpl, notify_function, Some(event_group))
+    }
+
+    fn new_with_closure_in_group<F>(
+        boot_services: &'a B,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: F,
+        event_group: Option<&'static efi::Guid>,
+    ) -> Result<Self, efi::Status>
+    where
+        F: FnMut(&Event<'a, B>) + 'static,
+    {
+        match event_group {
+            Some(_) => event_type.validate_for_group()?,
+            None => event_type.validate()?,
+        }
+        let notify_context = Box::into_raw(Box::new(ClosureContext { boot_services, notify_function }));
+        let trampoline = closure_trampoline::<B, F> as EventNotifyCallback<*mut c_void>;
+        //SAFETY: The context is a valid box that is only freed once the event is closed.
+        let event = unsafe {
+            match event_group {
+                Some(event_group) => boot_services.create_event_ex_unchecked(
+                    event_type,
+                    notify_tpl,
+                    trampoline,
+                    notify_context as *mut c_void,
+                    event_group,
+                ),
+                None => boot_services.create_event_unchecked(
+                    event_type,
+                    notify_tpl,
+                    Some(trampoline),
+                    notify_context as *mut c_void,
+                ),
+            }
+        };
+        match event {
+            Ok(event) => Ok(Self {
+                boot_services,
+                event,
+                notify_context: notify_context as *const c_void,
+                drop_notify_context: Some(drop_box::<ClosureContext<'a, B, F>>),
+            }),
+            Err(status) => {
+                //SAFETY: The event was not created, so nothing else use the context.
+                unsafe { drop(Box::from_raw(notify_context)) };
+                Err(status)
+            }
+        }
+    }
+
+    fn from_create_result<T: StaticPtr + 'static>(
+        boot_services: &'a B,
+        event: Result<efi::Event, efi::Status>,
+        notify_context: *const c_void,
+    ) -> Result<Self, efi::Status> {
+        match event {
+            Ok(event) => {
+                Ok(Self { boot_services, event, notify_context, drop_notify_context: Some(drop_static_ptr::<T>) })
+            }
+            Err(status) => {
+                //SAFETY: The context come from T::into_raw and the event was not created, so nothing else use it.
+                unsafe { drop_static_ptr::<T>(notify_context) };
+                Err(status)
+            }
+        }
+    }
+
+    /// Create an event from a raw event handle.
+    ///
+    /// The returned event does not own any notify context.
+    ///
+    /// # Safety
+    ///
+    /// *event* must be a valid event that is not owned by anything else, it will be closed when the returned value is dropped.
+    pub unsafe fn from_raw(boot_services: &'a B, event: efi::Event) -> Self {
+        Self { boot_services, event, notify_context: ptr::null(), drop_notify_context: None }
+    }
+
+    /// Consume the event and return the raw event handle without closing it.
+    ///
+    /// The notify context, if any, is leaked so it stay valid for the rest of the lifetime of the event.
+    pub fn into_raw(self) -> efi::Event {
+        let event = self.event;
+        mem::forget(self);
+        event
+    }
+
+    /// Return the raw event handle.
+    pub fn as_raw(&self) -> efi::Event {
+        self.event
+    }
+
+    /// Return the boot services used to create the event.
+    pub fn boot_services(&self) -> &'a B {
+        self.boot_services
+    }
+
+    /// Signal the event.
+    ///
+    /// See [`BootServices::signal_event`] for more details.
+    pub fn signal(&self) -> Result<(), efi::Status> {
+        self.boot_services.signal_event(self.event)
+    }
+
+    /// Check whether the event is in the signaled state.
+    ///
+    /// Return `Ok(false)` when the event is not signaled, see [`BootServices::check_event`] for more details.
+    pub fn check(&self) -> Result<bool, efi::Status> {
+        match self.boot_services.check_event(self.event) {
+            Ok(()) => Ok(true),
+            Err(efi::Status::NOT_READY) => Ok(false),
+            Err(status) => Err(status),
+        }
+    }
+
+    /// Set the type of timer and the trigger time for the event.
+    ///
+    /// See [`BootServices::set_timer`] for more details.
+    pub fn set_timer(&self, timer_type: EventTimerType, trigger_time: u64) -> Result<(), efi::Status> {
+        self.boot_services.set_timer(self.event, timer_type, trigger_time)
+    }
+
+    /// Close the event and return the status of the operation.
+    ///
+    /// The notify context is dropped only if the event was successfully closed.
+    pub fn close(mut self) -> Result<(), efi::Status> {
+        let status = self.close_inner();
+        mem::forget(self);
+        status
+    }
+
+    fn close_inner(&mut self) -> Result<(), efi::Status> {
+        self.boot_services.close_event(self.event)?;
+        if let Some(drop_notify_context) = self.drop_notify_context.take() {
+            //SAFETY: The event is closed, the notify function will not be called anymore with this context.
+            unsafe { drop_notify_context(self.notify_context) };
+        }
+        Ok(())
+    }
+}
+
+impl<B: BootServices> Drop for Event<'_, B> {
+    fn drop(&mut self) {
+        let _ = self.close_inner();
+    }
+}
+
+impl<B: BootServices> fmt::Debug for Event<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event").field("event", &self.event).field("notify_context", &self.notify_context).finish()
+    }
+}
+
+/// Recreate the [`StaticPtr`] from its raw pointer and drop it.
+///
+/// # Safety
+///
+/// *ptr* must come from [`StaticPtr::into_raw`] of a `T` and must not be used afterward.
+unsafe fn drop_static_ptr<T: StaticPtr>(ptr: *const c_void) {
+    T::drop_raw(ptr as *const T::Pointee);
+}
+
+/// Recreate the box from its raw pointer and drop it.
+///
+/// # Safety
+///
+/// *ptr* must come from [`Box::into_raw`] of a `Box<T>` and must not be used afterward.
+unsafe fn drop_box<T>(ptr: *const c_void) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+/// Notify context of an event created with [`Event::new_with_closure`].
+struct ClosureContext<'a, B: BootServices, F> {
+    boot_services: &'a B,
+    notify_function: F,
+}
+
+extern "efiapi" fn closure_trampoline<'a, B, F>(event: efi::Event, context: *mut c_void)
+where
+    B: BootServices + 'a,
+    F: FnMut(&Event<'a, B>),
+{
+    //SAFETY: The context is valid until the event is closed, and a notify function is never reentered for the same event.
+    let context = unsafe { &mut *(context as *mut ClosureContext<'a, B, F>) };
+    // The event is still owned by the Event returned by new_with_closure, it must not be closed here.
+    //SAFETY: The event is valid since it is being notified.
+    let event = ManuallyDrop::new(unsafe { Event::from_raw(context.boot_services, event) });
+    (context.notify_function)(&event);
+}
+
+/// Create an event that calls *notify_function* when *event_group* is signaled.
+///
+/// The event is closed, and the closure dropped, when the returned [`Event`] is dropped.
+/// Note that the closure is allocated with the global allocator, it must not be used after boot services are exited
+/// unless that memory stays valid (e.g. for [`EventGroup::VirtualAddressChange`]).
+pub fn on_event_group<'a, B, F>(
+    boot_services: &'a B,
+    event_group: EventGroup,
+    notify_tpl: Tpl,
+    notify_function: F,
+) -> Result<Event<'a, B>, efi::Status>
+where
+    B: BootServices,
+    F: FnMut(&Event<'a, B>) + 'static,
+{
+    Event::new_ex_with_closure(boot_services, EventType::NOTIFY_SIGNAL, notify_tpl, notify_function, event_group.guid())
+}
+
+/// Timer built on a [`EventType::TIMER`] event.
+///
+/// Durations are converted to the 100ns units used by [`BootServices::set_timer`], rounded up.
+pub struct Timer<'a, B: BootServices> {
+    event: Event<'a, B>,
+    armed: Cell<Option<(EventTimerType, u64)>>,
+}
+
+impl<'a, B: BootServices> Timer<'a, B> {
+    /// Create a timer that is not armed.
+    pub fn new(boot_services: &'a B) -> Result<Self, efi::Status> {
+        //SAFETY: There is no notify function and no notify context.
+        let event = unsafe {
+            boot_services.create_event_unchecked::<c_void>(EventType::TIMER, Tpl::CALLBACK, None, ptr::null_mut())
+        }?;
+        //SAFETY: The event was just created and is not owned by anything else.
+        Ok(Self { event: unsafe { Event::from_raw(boot_services, event) }, armed: Cell::new(None) })
+    }
+
+    /// Arm the timer to be signaled once after *duration*.
+    pub fn set_one_shot(&self, duration: Duration) -> Result<(), efi::Status> {
+        self.arm(EventTimerType::Relative, duration_to_trigger_time(duration))
+    }
+
+    /// Arm the timer to be signaled every *period*.
+    pub fn set_periodic(&self, period: Duration) -> Result<(), efi::Status> {
+        self.arm(EventTimerType::Periodic, duration_to_trigger_time(period))
+    }
+
+    /// Arm the timer again with the last one shot or periodic setting.
+    ///
+    /// Return [`efi::Status::NOT_STARTED`] if the timer was never armed.
+    pub fn rearm(&self) -> Result<(), efi::Status> {
+        let (timer_type, trigger_time) = self.armed.get().ok_or(efi::Status::NOT_STARTED)?;
+        self.event.set_timer(timer_type, trigger_time)
+    }
+
+    /// Cancel the timer, it can be armed again later with [`Self::rearm`].
+    pub fn cancel(&self) -> Result<(), efi::Status> {
+        self.event.set_timer(EventTimerType::Cancel, 0)
+    }
+
+    /// Block until the timer is signaled.
+    ///
+    /// Like [`BootServices::wait_for_event`], this must be called at [`Tpl::APPLICATION`].
+    pub fn wait(&self) -> Result<(), efi::Status> {
+        self.event.boot_services.wait_for_event(&mut [self.event.as_raw()]).map(|_| ())
+    }
+
+    /// Return true if the timer has been signaled since the last check.
+    pub fn is_signaled(&self) -> Result<bool, efi::Status> {
+        self.event.check()
+    }
+
+    /// Return the underlying event.
+    pub fn event(&self) -> &Event<'a, B> {
+        &self.event
+    }
+
+    fn arm(&self, timer_type: EventTimerType, trigger_time: u64) -> Result<(), efi::Status> {
+        self.event.set_timer(timer_type, trigger_time)?;
+        self.armed.set(Some((timer_type, trigger_time)));
+        Ok(())
+    }
+}
+
+impl<B: BootServices> fmt::Debug for Timer<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer").field("event", &self.event).field("armed", &self.armed.get()).finish()
+    }
+}
+
+/// Convert a duration to a trigger time in 100ns units, rounded up and saturated to [`u64::MAX`].
+pub(crate) fn duration_to_trigger_time(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos().div_ceil(100)).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Context(&'static AtomicUsize);
+
+    impl Drop for Context {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    extern "efiapi" fn notify(_event: efi::Event, context: *mut Context) {
+        assert!(!context.is_null());
+    }
+
+    #[test]
+    fn test_event_close_and_drop_context_on_drop() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event_unchecked::<Context>()
+            .withf(|event_type, notify_tpl, notify_function, notify_context| {
+                *event_type == EventType::NOTIFY_SIGNAL
+                    && *notify_tpl == Tpl::CALLBACK
+                    && notify_function.is_some()
+                    && !notify_context.is_null()
+            })
+            .returning(|_, _, _, _| Ok(1_usize as efi::Event));
+        boot_services.expect_close_event().withf(|event| *event as usize == 1).times(1).returning(|_| Ok(()));
+
+        let event = Event::new(
+            &boot_services,
+            EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            Some(notify),
+            Box::new(Context(&DROP_COUNT)),
+        )
+        .unwrap();
+        assert_eq!(1, event.as_raw() as usize);
+        assert_eq!(0, DROP_COUNT.load(Ordering::SeqCst));
+        drop(event);
+        assert_eq!(1, DROP_COUNT.load(Ordering::SeqCst));
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_event_notify_does_not_drop_context() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        static NOTIFY_FUNCTION: AtomicUsize = AtomicUsize::new(0);
+        static NOTIFY_CONTEXT: AtomicUsize = AtomicUsize::new(0);
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event_ex_unchecked::<Context>()
+            .withf(|_, _, _, _, event_group| event_group == &efi::EVENT_GROUP_READY_TO_BOOT)
+            .returning(|_, _, notify_function, notify_context, _| {
+                NOTIFY_FUNCTION.store(notify_function as usize, Ordering::SeqCst);
+                NOTIFY_CONTEXT.store(notify_context as usize, Ordering::SeqCst);
+                Ok(1_usize as efi::Event)
+            });
+        boot_services.expect_close_event().times(1).returning(|_| Ok(()));
+
+        let event = Event::new_ex(
+            &boot_services,
+            EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            notify,
+            Box::new(Context(&DROP_COUNT)),
+            EventGroup::ReadyToBoot.guid(),
+        )
+        .unwrap();
+
+        // Simulate the firmware notifying the event twice, the context is only borrowed.
+        let notify_function: EventNotifyCallback<*mut Context> =
+            unsafe { mem::transmute(NOTIFY_FUNCTION.load(Ordering::SeqCst)) };
+        notify_function(event.as_raw(), NOTIFY_CONTEXT.load(Ordering::SeqCst) as *mut Context);
+        notify_function(event.as_raw(), NOTIFY_CONTEXT.load(Ordering::SeqCst) as *mut Context);
+        assert_eq!(0, DROP_COUNT.load(Ordering::SeqCst));
+        drop(event);
+        assert_eq!(1, DROP_COUNT.load(Ordering::SeqCst));
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_event_context_is_dropped_if_create_fail() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event_unchecked::<Context>()
+            .returning(|_, _, _, _| Err(efi::Status::INVALID_PARAMETER));
+        boot_services.expect_close_event().never();
+
+        let status = Event::new(
+            &boot_services,
+            EventType::NOTIFY_SIGNAL,
+            Tpl::CALLBACK,
+            Some(notify),
+            Box::new(Context(&DROP_COUNT)),
+        )
+        .unwrap_err();
+        assert_eq!(efi::Status::INVALID_PARAMETER, status);
+        assert_eq!(1, DROP_COUNT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_event_context_is_kept_if_close_fail() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_create_event_unchecked::<Context>().returning(|_, _, _, _| Ok(1_usize as efi::Event));
+        boot_services.expect_close_event().times(1).returning(|_| Err(efi::Status::INVALID_PARAMETER));
+
+        let event =
+            Event::new(&boot_services, EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, None, Box::new(Context(&DROP_COUNT)))
+                .unwrap();
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), event.close());
+        assert_eq!(0, DROP_COUNT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_event_check_signal_and_set_timer() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_signal_event().withf(|event| *event as usize == 1).times(1).returning(|_| Ok(()));
+        let mut seq = mockall::Sequence::new();
+        boot_services.expect_check_event().times(1).in_sequence(&mut seq).returning(|_| Err(efi::Status::NOT_READY));
+        boot_services.expect_check_event().times(1).in_sequence(&mut seq).returning(|_| Ok(()));
+        boot_services
+            .expect_check_event()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Err(efi::Status::INVALID_PARAMETER));
+        boot_services
+            .expect_set_timer()
+            .withf(|event, timer_type, trigger_time| {
+                *event as usize == 1 && matches!(timer_type, EventTimerType::Relative) && *trigger_time == 10
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        boot_services.expect_close_event().times(1).returning(|_| Ok(()));
+
+        let event = unsafe { Event::from_raw(&boot_services, 1_usize as efi::Event) };
+        assert_eq!(Ok(false), event.check());
+        event.signal().unwrap();
+        assert_eq!(Ok(true), event.check());
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), event.check());
+        event.set_timer(EventTimerType::Relative, 10).unwrap();
+    }
+
+    #[test]
+    fn test_event_into_raw_does_not_close() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_create_event_unchecked::<()>().returning(|_, _, _, _| Ok(1_usize as efi::Event));
+        boot_services.expect_close_event().never();
+
+        static CONTEXT: () = ();
+        let event = Event::new(&boot_services, EventType::TIMER, Tpl::APPLICATION, None, &CONTEXT).unwrap();
+        assert_eq!(1, event.into_raw() as usize);
+    }
+
+    #[test]
+    fn test_event_with_closure() {
+        let mut boot_services = MockBootServices::new();
+        static NOTIFY_FUNCTION: AtomicUsize = AtomicUsize::new(0);
+        static NOTIFY_CONTEXT: AtomicUsize = AtomicUsize::new(0);
+        boot_services.expect_create_event_unchecked::<c_void>().returning(|_, _, notify_function, notify_context| {
+            NOTIFY_FUNCTION.store(notify_function.unwrap() as usize, Ordering::SeqCst);
+            NOTIFY_CONTEXT.store(notify_context as usize, Ordering::SeqCst);
+            Ok(1_usize as efi::Event)
+        });
+        boot_services.expect_close_event().withf(|event| *event as usize == 1).times(1).returning(|_| Ok(()));
+
+        let calls = Rc::new(Cell::new(0));
+        let dropped = Rc::new(Cell::new(false));
+        struct DropFlag(Rc<Cell<bool>>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let event = {
+            let calls = calls.clone();
+            let drop_flag = DropFlag(dropped.clone());
+            Event::new_with_closure(&boot_services, EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, move |event| {
+                let _ = &drop_flag;
+                assert_eq!(1, event.as_raw() as usize);
+                calls.set(calls.get() + 1);
+            })
+            .unwrap()
+        };
+
+        // Simulate the firmware notifying the event twice.
+        let notify_function: EventNotifyCallback<*mut c_void> =
+            unsafe { mem::transmute(NOTIFY_FUNCTION.load(Ordering::SeqCst)) };
+        notify_function(event.as_raw(), NOTIFY_CONTEXT.load(Ordering::SeqCst) as *mut c_void);
+        notify_function(event.as_raw(), NOTIFY_CONTEXT.load(Ordering::SeqCst) as *mut c_void);
+        assert_eq!(2, calls.get());
+
+        assert!(!dropped.get());
+        drop(event);
+        assert!(dropped.get());
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_duration_to_trigger_time() {
+        assert_eq!(0, duration_to_trigger_time(Duration::ZERO));
+        assert_eq!(1, duration_to_trigger_time(Duration::from_nanos(1)));
+        assert_eq!(1, duration_to_trigger_time(Duration::from_nanos(100)));
+        assert_eq!(10_000, duration_to_trigger_time(Duration::from_millis(1)));
+        assert_eq!(10_000_000, duration_to_trigger_time(Duration::from_secs(1)));
+        assert_eq!(u64::MAX, duration_to_trigger_time(Duration::MAX));
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event_unchecked::<c_void>()
+            .withf(|event_type, _, notify_function, notify_context| {
+                *event_type == EventType::TIMER && notify_function.is_none() && notify_context.is_null()
+            })
+            .returning(|_, _, _, _| Ok(1_usize as efi::Event));
+        let mut seq = mockall::Sequence::new();
+        boot_services
+            .expect_set_timer()
+            .withf(|event, timer_type, trigger_time| {
+                *event as usize == 1 && matches!(timer_type, EventTimerType::Relative) && *trigger_time == 5_000_000
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(()));
+        boot_services
+            .expect_wait_for_event()
+            .withf(|events| events.len() == 1 && events[0] as usize == 1)
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_| Ok(0));
+        boot_services
+            .expect_set_timer()
+            .withf(|_, timer_type, trigger_time| matches!(timer_type, EventTimerType::Cancel) && *trigger_time == 0)
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(()));
+        boot_services
+            .expect_set_timer()
+            .withf(|_, timer_type, trigger_time| {
+                matches!(timer_type, EventTimerType::Relative) && *trigger_time == 5_000_000
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(()));
+        boot_services
+            .expect_set_timer()
+            .withf(|_, timer_type, trigger_time| {
+                matches!(timer_type, EventTimerType::Periodic) && *trigger_time == 10_000
+            })
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(()));
+        boot_services.expect_close_event().withf(|event| *event as usize == 1).times(1).returning(|_| Ok(()));
+
+        let timer = Timer::new(&boot_services).unwrap();
+        assert_eq!(Err(efi::Status::NOT_STARTED), timer.rearm());
+        timer.set_one_shot(Duration::from_millis(500)).unwrap();
+        timer.wait().unwrap();
+        timer.cancel().unwrap();
+        timer.rearm().unwrap();
+        timer.set_periodic(Duration::from_millis(1)).unwrap();
+        drop(timer);
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_timer_failed_arm_is_not_remembered() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_create_event_unchecked::<c_void>().returning(|_, _, _, _| Ok(1_usize as efi::Event));
+        boot_services.expect_set_timer().times(1).returning(|_, _, _| Err(efi::Status::INVALID_PARAMETER));
+        boot_services.expect_close_event().times(1).returning(|_| Ok(()));
+
+        let timer = Timer::new(&boot_services).unwrap();
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), timer.set_periodic(Duration::from_secs(1)));
+        assert_eq!(Err(efi::Status::NOT_STARTED), timer.rearm());
+    }
+
+    #[test]
+    fn test_event_type_validate() {
+        let valid = [
+            EventType::NONE,
+            EventType::TIMER,
+            EventType::TIMER | EventType::NOTIFY_SIGNAL,
+            EventType::TIMER | EventType::NOTIFY_WAIT,
+            EventType::RUNTIME | EventType::NOTIFY_SIGNAL,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES,
+            EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+        ];
+        for event_type in valid {
+            assert_eq!(Ok(()), event_type.validate(), "{event_type:?}");
+        }
+        let invalid = [
+            EventType(0x8),
+            EventType::NOTIFY_WAIT | EventType::NOTIFY_SIGNAL,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES | EventType::TIMER,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES | EventType::RUNTIME,
+            EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE | EventType::TIMER,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES | EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE,
+            EventType(efi::EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE & !efi::EVT_RUNTIME),
+        ];
+        for event_type in invalid {
+            assert_eq!(Err(efi::Status::INVALID_PARAMETER), event_type.validate(), "{event_type:?}");
+        }
+        assert_eq!(Ok(()), EventType::NOTIFY_SIGNAL.validate_for_group());
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), EventType::SIGNAL_EXIT_BOOT_SERVICES.validate_for_group());
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), EventType::SIGNAL_VIRTUAL_ADDRESS_CHANGE.validate_for_group());
+    }
+
+    #[test]
+    fn test_event_with_invalid_type_is_not_created() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_create_event_unchecked::<Context>().never();
+        boot_services.expect_create_event_ex_unchecked::<c_void>().never();
+
+        let status = Event::new(
+            &boot_services,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES | EventType::TIMER,
+            Tpl::CALLBACK,
+            Some(notify),
+            Box::new(Context(&DROP_COUNT)),
+        )
+        .unwrap_err();
+        assert_eq!(efi::Status::INVALID_PARAMETER, status);
+        assert_eq!(1, DROP_COUNT.load(Ordering::SeqCst));
+
+        let status = Event::new_ex_with_closure(
+            &boot_services,
+            EventType::SIGNAL_EXIT_BOOT_SERVICES,
+            Tpl::CALLBACK,
+            |_| (),
+            EventGroup::ReadyToBoot.guid(),
+        )
+        .unwrap_err();
+        assert_eq!(efi::Status::INVALID_PARAMETER, status);
+    }
+
+    #[test]
+    fn test_on_event_group() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_create_event_ex_unchecked::<c_void>()
+            .withf(|event_type, notify_tpl, _, notify_context, event_group| {
+                *event_type == EventType::NOTIFY_SIGNAL
+                    && *notify_tpl == Tpl::NOTIFY
+                    && !notify_context.is_null()
+                    && event_group == &efi::EVENT_GROUP_READY_TO_BOOT
+            })
+            .times(1)
+            .returning(|_, _, _, _, _| Ok(1_usize as efi::Event));
+        boot_services.expect_close_event().withf(|event| *event as usize == 1).times(1).returning(|_| Ok(()));
+
+        let event = on_event_group(&boot_services, EventGroup::ReadyToBoot, Tpl::NOTIFY, |_| ()).unwrap();
+        assert_eq!(1, event.as_raw() as usize);
+        drop(event);
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_event_group_guid() {
+        assert_eq!(&efi::EVENT_GROUP_EXIT_BOOT_SERVICES, EventGroup::ExitBootServices.guid());
+        assert_eq!(&efi::EVENT_GROUP_BEFORE_EXIT_BOOT_SERVICES, EventGroup::BeforeExitBootServices.guid());
+        assert_eq!(&efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE, EventGroup::VirtualAddressChange.guid());
+        assert_eq!(&efi::EVENT_GROUP_MEMORY_MAP_CHANGE, EventGroup::MemoryMapChange.guid());
+        assert_eq!(&efi::EVENT_GROUP_READY_TO_BOOT, EventGroup::ReadyToBoot.guid());
+        assert_eq!(&efi::EVENT_GROUP_AFTER_READY_TO_BOOT, EventGroup::AfterReadyToBoot.guid());
+        assert_eq!(&efi::EVENT_GROUP_RESET_SYSTEM, <&efi::Guid>::from(EventGroup::ResetSystem));
+    }
+}
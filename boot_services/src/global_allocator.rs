@@ -1,64 +1,386 @@
+//! Global allocator backed by boot services memory services.
+//!
+//! ```ignore
+//! static BOOT_SERVICES: StandardBootServices = StandardBootServices::new_uninit();
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: BootServicesGlobalAllocator<StandardBootServices> =
+//!     BootServicesGlobalAllocator::new(&BOOT_SERVICES).with_memory_type(MemoryType::LOADER_DATA);
+//! ```
+
 use core::{
     alloc::{GlobalAlloc, Layout},
+    cmp,
     ops::Deref,
-    ptr,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use super::MemoryType;
-use crate::BootServices;
+use r_efi::efi;
+
+use crate::{
+    allocation::{MemoryType, UEFI_PAGE_SIZE},
+    allocator::BootServicesAllocator,
+    event::EventType,
+    pages::Pages,
+    tpl::Tpl,
+    BootServices,
+};
+
+/// Allocations of at least this size are made with pages instead of pool.
+const PAGE_ALLOCATION_THRESHOLD: usize = 0x10 * UEFI_PAGE_SIZE;
+
+/// Allocator used by [`BootServicesGlobalAllocator`] once boot services are exited.
+///
+/// # Safety
+///
+/// [`FallbackAllocator::owns`] must return true only for pointers returned by this allocator, since memory allocated
+/// with boot services before the switch is never given to the fallback allocator.
+pub unsafe trait FallbackAllocator: GlobalAlloc + Sync {
+    /// Return true if *ptr* was allocated by this allocator.
+    fn owns(&self, ptr: *const u8) -> bool;
+}
 
-pub struct BootServicesGlobalAllocator<T: BootServices + 'static>(pub &'static T);
+/// Global allocator using pool memory for small allocations and pages for large or page aligned ones.
+///
+/// Once boot services are exited, see [`Self::notify_exit_boot_services`], the allocations are forwarded to the
+/// fallback allocator if any, otherwise any allocation fails and returns a null pointer, so the caller goes through
+/// `handle_alloc_error`. Memory allocated with boot services before the switch is leaked when freed.
+pub struct BootServicesGlobalAllocator<T: BootServices + 'static> {
+    boot_services: &'static T,
+    memory_type: MemoryType,
+    fallback: Option<&'static dyn FallbackAllocator>,
+    exited: AtomicBool,
+}
 
 impl<T: BootServices> Deref for BootServicesGlobalAllocator<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        self.boot_services
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    Pool,
+    AlignedPool,
+    Pages,
+}
+
+impl Strategy {
+    fn new(layout: Layout) -> Self {
+        if layout.align() >= UEFI_PAGE_SIZE || layout.size() >= PAGE_ALLOCATION_THRESHOLD {
+            Strategy::Pages
+        } else if layout.align() <= 8 {
+            // Pool allocations are 8 bytes aligned, the size of a pool allocation does not matter to free it.
+            Strategy::Pool
+        } else {
+            Strategy::AlignedPool
+        }
     }
 }
 
 impl<T: BootServices> BootServicesGlobalAllocator<T> {
+    /// Create an allocator that allocates [`MemoryType::BOOT_SERVICES_DATA`] memory.
+    pub const fn new(boot_services: &'static T) -> Self {
+        Self {
+            boot_services,
+            memory_type: MemoryType::BOOT_SERVICES_DATA,
+            fallback: None,
+            exited: AtomicBool::new(false),
+        }
+    }
+
+    /// Set the type of memory to allocate.
+    pub const fn with_memory_type(mut self, memory_type: MemoryType) -> Self {
+        self.memory_type = memory_type;
+        self
+    }
+
+    /// Set the allocator to use once boot services are exited.
+    pub const fn with_fallback(mut self, fallback: &'static dyn FallbackAllocator) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Type of memory allocated.
+    pub fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+
+    /// Switch to the fallback allocator, boot services are not used anymore after this call.
+    pub fn notify_exit_boot_services(&self) {
+        self.exited.store(true, Ordering::SeqCst);
+    }
+
+    /// Return true if the allocator does not use boot services anymore.
+    pub fn is_exited(&self) -> bool {
+        self.exited.load(Ordering::SeqCst)
+    }
+
+    /// Create an event that calls [`Self::notify_exit_boot_services`] when boot services are exited.
+    pub fn register_exit_boot_services_event(&'static self) -> Result<efi::Event, efi::Status>
+    where
+        Self: Sync,
+    {
+        extern "efiapi" fn notify<T: BootServices + 'static>(
+            _event: efi::Event,
+            allocator: &'static BootServicesGlobalAllocator<T>,
+        ) {
+            allocator.notify_exit_boot_services();
+        }
+        self.boot_services.create_event(EventType::SIGNAL_EXIT_BOOT_SERVICES, Tpl::NOTIFY, Some(notify::<T>), self)
+    }
+
+    fn pool_allocator(&self) -> BootServicesAllocator<'static, T> {
+        BootServicesAllocator::new(self.boot_services, self.memory_type)
+    }
+
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match layout.align() {
-            0..=8 => self.allocate_pool(MemoryType::BootServicesData, layout.size()).unwrap_or(ptr::null_mut()),
-            _ => {
-                let Ok((extended_layout, tracker_offset)) = layout.extend(Layout::new::<*mut *mut u8>()) else {
-                    return ptr::null_mut();
-                };
-                let alloc_size = extended_layout.align() + extended_layout.size();
-                let Ok(original_ptr) = self.allocate_pool(MemoryType::BootServicesData, alloc_size) else {
-                    return ptr::null_mut();
-                };
-                let ptr = original_ptr.add(original_ptr.align_offset(extended_layout.align()));
-                let tracker_ptr = ptr.add(tracker_offset) as *mut *mut u8;
-                ptr::write(tracker_ptr, original_ptr);
-                ptr
+        if self.is_exited() {
+            return match self.fallback {
+                Some(fallback) => fallback.alloc(layout),
+                // An allocator must not unwind, the caller reports the failure with `handle_alloc_error`.
+                None => ptr::null_mut(),
+            };
+        }
+        match Strategy::new(layout) {
+            Strategy::Pool | Strategy::AlignedPool => {
+                self.pool_allocator().allocate(layout).map_or(ptr::null_mut(), |ptr| ptr.as_ptr() as *mut u8)
             }
+            Strategy::Pages => self.alloc_pages(layout),
         }
     }
 
+    unsafe fn alloc_pages(&self, layout: Layout) -> *mut u8 {
+        let nb_pages = layout.size().div_ceil(UEFI_PAGE_SIZE);
+        let alignment = cmp::max(layout.align(), UEFI_PAGE_SIZE);
+        Pages::allocate_aligned(self.boot_services, self.memory_type, nb_pages, alignment)
+            .map_or(ptr::null_mut(), |pages| pages.leak().as_mut_ptr())
+    }
+
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        match layout.align() {
-            0..=8 => _ = self.free_pool(ptr),
-            _ => {
-                let Ok((extended_layout, tracker_offset)) = layout.extend(Layout::new::<*mut *mut u8>()) else {
-                    return;
-                };
-                let tracker_ptr = ptr.add(tracker_offset) as *mut *mut u8;
-                let original_ptr = ptr::read(tracker_ptr);
-                debug_assert_eq!(ptr, original_ptr.add(original_ptr.align_offset(extended_layout.align())));
-                let _ = self.free_pool(original_ptr);
+        if self.is_exited() {
+            if let Some(fallback) = self.fallback.filter(|fallback| fallback.owns(ptr)) {
+                fallback.dealloc(ptr, layout);
+            }
+            // Boot services memory can not be freed anymore.
+            return;
+        }
+        match Strategy::new(layout) {
+            Strategy::Pool | Strategy::AlignedPool => {
+                if let Some(ptr) = NonNull::new(ptr) {
+                    let _ = self.pool_allocator().deallocate(ptr, layout);
+                }
+            }
+            Strategy::Pages => _ = self.free_pages(ptr as usize, layout.size().div_ceil(UEFI_PAGE_SIZE)),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return ptr::null_mut();
+        };
+        if self.is_exited() {
+            if let Some(fallback) = self.fallback.filter(|fallback| fallback.owns(ptr)) {
+                return fallback.realloc(ptr, layout, new_size);
+            }
+        } else {
+            let reuse = match (Strategy::new(layout), Strategy::new(new_layout)) {
+                (Strategy::Pool, Strategy::Pool) => new_size <= layout.size(),
+                (Strategy::Pages, Strategy::Pages) => {
+                    layout.size().div_ceil(UEFI_PAGE_SIZE) == new_size.div_ceil(UEFI_PAGE_SIZE)
+                }
+                _ => false,
+            };
+            if reuse {
+                return ptr;
             }
         }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
     }
 }
 
 unsafe impl<T: BootServices> GlobalAlloc for BootServicesGlobalAllocator<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        BootServicesGlobalAllocator::alloc(&self, layout)
+        BootServicesGlobalAllocator::alloc(self, layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        BootServicesGlobalAllocator::dealloc(&self, ptr, layout)
+        BootServicesGlobalAllocator::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        BootServicesGlobalAllocator::realloc(self, ptr, layout, new_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{allocation::AllocType, MockBootServices};
+    use alloc::boxed::Box;
+    use mockall::predicate::eq;
+    use std::alloc::System;
+
+    fn boot_services(setup: impl FnOnce(&mut MockBootServices)) -> &'static MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        setup(&mut boot_services);
+        Box::leak(Box::new(boot_services))
+    }
+
+    fn expect_pool(boot_services: &mut MockBootServices, memory_type: MemoryType) {
+        boot_services
+            .expect_allocate_pool()
+            .with(eq(memory_type), mockall::predicate::always())
+            .returning(|_, size| Ok(unsafe { System.alloc(Layout::from_size_align(size, 8).unwrap()) }));
+        boot_services.expect_free_pool().returning(|_| Ok(()));
+    }
+
+    /// Fallback allocator owning the pointers above 0x1000.
+    struct Fallback;
+
+    unsafe impl GlobalAlloc for Fallback {
+        unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+            0x2000 as *mut u8
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+            assert_eq!(0x2000, ptr as usize);
+        }
+    }
+
+    unsafe impl FallbackAllocator for Fallback {
+        fn owns(&self, ptr: *const u8) -> bool {
+            ptr as usize >= 0x1000
+        }
+    }
+
+    #[test]
+    fn test_pool_allocation() {
+        let boot_services = boot_services(|boot_services| expect_pool(boot_services, MemoryType::LOADER_DATA));
+        let allocator = BootServicesGlobalAllocator::new(boot_services).with_memory_type(MemoryType::LOADER_DATA);
+        assert_eq!(MemoryType::LOADER_DATA, allocator.memory_type());
+
+        for align in [1, 8, 16, 256] {
+            let layout = Layout::from_size_align(100, align).unwrap();
+            let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % align);
+            unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn test_page_allocation() {
+        let boot_services = boot_services(|boot_services| {
+            boot_services
+                .expect_allocate_pages()
+                .withf(|alloc_type, memory_type, nb_pages| {
+                    matches!(alloc_type, AllocType::AnyPage)
+                        && *memory_type == MemoryType::BOOT_SERVICES_DATA
+                        && *nb_pages == 0x11
+                })
+                .times(1)
+                .returning(|_, _, _| Ok(0x100000));
+            boot_services.expect_free_pages().with(eq(0x100000), eq(0x11)).times(1).returning(|_, _| Ok(()));
+        });
+        let allocator = BootServicesGlobalAllocator::new(boot_services);
+
+        let layout = Layout::from_size_align(PAGE_ALLOCATION_THRESHOLD + 1, 8).unwrap();
+        let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+        assert_eq!(0x100000, ptr as usize);
+        unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+    }
+
+    #[test]
+    fn test_page_allocation_with_big_alignment_is_trimmed() {
+        let boot_services = boot_services(|boot_services| {
+            let mut seq = mockall::Sequence::new();
+            // One page aligned on 4 pages needs 3 extra pages.
+            boot_services
+                .expect_allocate_pages()
+                .withf(|_, _, nb_pages| *nb_pages == 4)
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _, _| Ok(0x12000));
+            boot_services
+                .expect_free_pages()
+                .with(eq(0x12000), eq(2))
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _| Ok(()));
+            boot_services
+                .expect_free_pages()
+                .with(eq(0x15000), eq(1))
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _| Ok(()));
+            boot_services
+                .expect_free_pages()
+                .with(eq(0x14000), eq(1))
+                .times(1)
+                .in_sequence(&mut seq)
+                .returning(|_, _| Ok(()));
+        });
+        let allocator = BootServicesGlobalAllocator::new(boot_services);
+
+        let layout = Layout::from_size_align(0x100, 0x4000).unwrap();
+        let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+        assert_eq!(0x14000, ptr as usize);
+        unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+    }
+
+    #[test]
+    fn test_realloc() {
+        let boot_services = boot_services(|boot_services| expect_pool(boot_services, MemoryType::BOOT_SERVICES_DATA));
+        let allocator = BootServicesGlobalAllocator::new(boot_services);
+
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+        unsafe { ptr::copy_nonoverlapping([1_u8; 16].as_ptr(), ptr, 16) };
+        // Shrinking a pool allocation keeps the same pointer.
+        assert_eq!(ptr, unsafe { GlobalAlloc::realloc(&allocator, ptr, layout, 8) });
+
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let new_ptr = unsafe { GlobalAlloc::realloc(&allocator, ptr, layout, 64) };
+        assert_ne!(ptr, new_ptr);
+        assert_eq!([1_u8; 8], unsafe { *(new_ptr as *const [u8; 8]) });
+        unsafe { GlobalAlloc::dealloc(&allocator, new_ptr, Layout::from_size_align(64, 8).unwrap()) };
+    }
+
+    #[test]
+    fn test_alloc_after_exit_boot_services_without_fallback() {
+        let boot_services = boot_services(|boot_services| {
+            boot_services.expect_allocate_pool().never();
+        });
+        let allocator = BootServicesGlobalAllocator::new(boot_services);
+        allocator.notify_exit_boot_services();
+        assert!(allocator.is_exited());
+        assert!(unsafe { GlobalAlloc::alloc(&allocator, Layout::new::<u64>()) }.is_null());
+    }
+
+    #[test]
+    fn test_fallback_after_exit_boot_services() {
+        static FALLBACK: Fallback = Fallback;
+        let boot_services = boot_services(|boot_services| {
+            boot_services.expect_allocate_pool().never();
+            boot_services.expect_free_pool().never();
+        });
+        let allocator = BootServicesGlobalAllocator::new(boot_services).with_fallback(&FALLBACK);
+        allocator.notify_exit_boot_services();
+
+        let layout = Layout::new::<u64>();
+        let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+        assert_eq!(0x2000, ptr as usize);
+        unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+        // Memory allocated before exit boot services is leaked.
+        unsafe { GlobalAlloc::dealloc(&allocator, 0x800 as *mut u8, layout) };
     }
 }
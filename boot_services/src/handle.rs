@@ -0,0 +1,251 @@
+//! Typed wrapper around [`efi::Handle`] to query the protocol database.
+
+use core::{ffi::c_void, fmt, ptr};
+
+use r_efi::efi;
+
+use crate::{
+    boxed::BootServicesBox,
+    protocol_handler::{HandleSearchType, OpenProtocolAttributes, OpenProtocolInformationEntry, Protocol},
+    BootServices,
+};
+
+/// Handle of the UEFI handle database.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Handle(efi::Handle);
+
+// SAFETY: A handle is an opaque identifier, it is never dereferenced by this crate.
+unsafe impl Send for Handle {}
+// SAFETY: A handle is an opaque identifier, it is never dereferenced by this crate.
+unsafe impl Sync for Handle {}
+
+impl Handle {
+    /// Null handle, used for example when no controller handle is required.
+    pub const NULL: Handle = Handle(ptr::null_mut());
+
+    /// Wrap a raw handle returned by the firmware.
+    pub const fn new(handle: efi::Handle) -> Self {
+        Self(handle)
+    }
+
+    /// Return the raw handle to give to the firmware.
+    pub const fn as_ptr(self) -> efi::Handle {
+        self.0
+    }
+
+    /// Return true if this is the [`Handle::NULL`] handle.
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+
+    /// Return the GUIDs of the protocols installed on this handle.
+    ///
+    /// See [`BootServices::protocols_per_handle`].
+    pub fn protocols<B: BootServices>(
+        self,
+        boot_services: &B,
+    ) -> Result<BootServicesBox<'_, [efi::Guid], B>, efi::Status> {
+        boot_services.protocols_per_handle(self.0)
+    }
+
+    /// Return true if *protocol* is installed on this handle.
+    pub fn supports<P: Protocol + 'static, B: BootServices>(self, boot_services: &B, protocol: &P) -> bool {
+        // SAFETY: The interface is not used.
+        unsafe { boot_services.handle_protocol_unchecked(self.0, protocol.protocol_guid()).is_ok() }
+    }
+
+    /// Open *protocol* on this handle on behalf of *agent_handle* and *controller_handle*.
+    ///
+    /// See [`BootServices::open_protocol`].
+    pub fn open<P: Protocol + 'static, B: BootServices>(
+        self,
+        boot_services: &B,
+        protocol: &P,
+        agent_handle: Handle,
+        controller_handle: Handle,
+        attribute: OpenProtocolAttributes,
+    ) -> Result<Option<&'static mut P::Interface>, efi::Status>
+    where
+        P::Interface: 'static,
+    {
+        boot_services.open_protocol(self.0, protocol, agent_handle.0, controller_handle.0, attribute)
+    }
+
+    /// Return the agents that currently have *protocol* opened on this handle.
+    ///
+    /// See [`BootServices::open_protocol_information`].
+    pub fn open_information<'a, P: Protocol + 'static, B: BootServices>(
+        self,
+        boot_services: &'a B,
+        protocol: &P,
+    ) -> Result<BootServicesBox<'a, [OpenProtocolInformationEntry], B>, efi::Status> {
+        boot_services.open_protocol_information(self.0, protocol.protocol_guid())
+    }
+}
+
+impl From<efi::Handle> for Handle {
+    fn from(handle: efi::Handle) -> Self {
+        Self(handle)
+    }
+}
+
+impl From<Handle> for efi::Handle {
+    fn from(handle: Handle) -> Self {
+        handle.0
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({:p})", self.0)
+    }
+}
+
+impl fmt::Pointer for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&(self.0 as *const c_void), f)
+    }
+}
+
+/// Write every handle of the handle database with the GUIDs of its protocols, similar to the UEFI shell `dh`
+/// command.
+///
+/// Handles uninstalled while the database is walked are skipped. An error writing to *writer* is reported as
+/// [`efi::Status::DEVICE_ERROR`].
+///
+/// ```text
+/// Handle 0x7f5e3c18:
+///   5B1B31A1-9562-11D2-8E3F-00A0C969723B
+///   BC62157E-3E33-4FEC-9920-2D3B36D750DF
+/// ```
+pub fn dump_handle_database<B: BootServices, W: fmt::Write>(
+    boot_services: &B,
+    writer: &mut W,
+) -> Result<(), efi::Status> {
+    let handles = boot_services.locate_handle(HandleSearchType::AllHandle)?;
+    for &handle in handles.iter() {
+        let handle = Handle::new(handle);
+        let Ok(protocols) = handle.protocols(boot_services) else {
+            continue;
+        };
+        writeln!(writer, "Handle {handle:p}:").map_err(|_| efi::Status::DEVICE_ERROR)?;
+        for guid in protocols.iter() {
+            writeln!(writer, "  {}", GuidDisplay(guid)).map_err(|_| efi::Status::DEVICE_ERROR)?;
+        }
+    }
+    Ok(())
+}
+
+/// Display a GUID in its registry format.
+struct GuidDisplay<'a>(&'a efi::Guid);
+
+impl fmt::Display for GuidDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, node) = self.0.as_fields();
+        write!(f, "{time_low:08X}-{time_mid:04X}-{time_hi:04X}-{clk_seq_hi:02X}{clk_seq_low:02X}-")?;
+        node.iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{boxed::test::boxed_slice, protocol_handler::BlockIo, MockBootServices};
+    use alloc::string::String;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_handle() {
+        let handle = Handle::new(0x1000 as efi::Handle);
+        assert_eq!(0x1000 as efi::Handle, efi::Handle::from(handle));
+        assert_eq!(handle, Handle::from(0x1000 as efi::Handle));
+        assert_eq!("Handle(0x1000)", format!("{handle:?}"));
+        assert!(!handle.is_null());
+        assert!(Handle::NULL.is_null());
+        assert_eq!(2, HashSet::from([handle, Handle::NULL, handle]).len());
+    }
+
+    #[test]
+    fn test_handle_protocols() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_protocols_per_handle()
+            .withf(|handle| *handle as usize == 1)
+            .returning(|_| Ok(boxed_slice(vec![efi::protocols::block_io::PROTOCOL_GUID])));
+        boot_services.expect_handle_protocol_unchecked().returning(|handle, protocol| {
+            match (handle as usize, *protocol == efi::protocols::block_io::PROTOCOL_GUID) {
+                (1, true) => Ok(0x10 as *mut c_void),
+                _ => Err(efi::Status::UNSUPPORTED),
+            }
+        });
+        boot_services
+            .expect_open_protocol_information()
+            .withf(|handle, protocol| *handle as usize == 1 && *protocol == efi::protocols::block_io::PROTOCOL_GUID)
+            .returning(|_, _| {
+                Ok(boxed_slice(vec![OpenProtocolInformationEntry {
+                    agent_handle: 0x20 as efi::Handle,
+                    controller_handle: ptr::null_mut(),
+                    attributes: OpenProtocolAttributes::BY_DRIVER,
+                    open_count: 1,
+                }]))
+            });
+
+        let handle = Handle::new(1 as efi::Handle);
+        assert_eq!(&[efi::protocols::block_io::PROTOCOL_GUID], &*handle.protocols(&boot_services).unwrap());
+        assert!(handle.supports(&boot_services, &BlockIo));
+        assert!(!Handle::new(2 as efi::Handle).supports(&boot_services, &BlockIo));
+        let information = handle.open_information(&boot_services, &BlockIo).unwrap();
+        assert_eq!(1, information.len());
+        assert_eq!(0x20, information[0].agent_handle as usize);
+    }
+
+    #[test]
+    fn test_handle_open() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_open_protocol::<BlockIo, efi::protocols::block_io::Protocol>()
+            .withf(|handle, _, agent_handle, controller_handle, attribute| {
+                *handle as usize == 1
+                    && *agent_handle as usize == 2
+                    && controller_handle.is_null()
+                    && *attribute == OpenProtocolAttributes::BY_DRIVER
+            })
+            .returning(|_, _, _, _, _| Ok(None));
+
+        let interface = Handle::new(1 as efi::Handle).open(
+            &boot_services,
+            &BlockIo,
+            Handle::new(2 as efi::Handle),
+            Handle::NULL,
+            OpenProtocolAttributes::BY_DRIVER,
+        );
+        assert!(matches!(interface, Ok(None)));
+    }
+
+    #[test]
+    fn test_dump_handle_database() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_locate_handle()
+            .withf(|search_type| matches!(search_type, HandleSearchType::AllHandle))
+            .returning(|_| Ok(boxed_slice(vec![0x1000 as efi::Handle, 0x2000 as efi::Handle, 0x3000 as efi::Handle])));
+        boot_services.expect_protocols_per_handle().returning(|handle| match handle as usize {
+            0x1000 => Ok(boxed_slice(vec![
+                efi::protocols::loaded_image::PROTOCOL_GUID,
+                efi::protocols::device_path::PROTOCOL_GUID,
+            ])),
+            // Handle uninstalled while walking the database.
+            0x2000 => Err(efi::Status::INVALID_PARAMETER),
+            _ => Ok(boxed_slice(vec![efi::protocols::block_io::PROTOCOL_GUID])),
+        });
+
+        let mut output = String::new();
+        dump_handle_database(&boot_services, &mut output).unwrap();
+        assert_eq!(
+            "Handle 0x1000:\n  5B1B31A1-9562-11D2-8E3F-00A0C969723B\n  09576E91-6D3F-11D2-8E39-00A0C969723B\n\
+             Handle 0x3000:\n  964E5B21-6459-11D2-8E39-00A0C969723B\n",
+            output
+        );
+    }
+}
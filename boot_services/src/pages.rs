@@ -0,0 +1,258 @@
+//! Owned pages allocated with [`BootServices::allocate_pages`].
+
+use core::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+use r_efi::efi;
+
+use crate::{
+    allocation::{AllocType, MemoryType, PhysicalAddress, UEFI_PAGE_SIZE},
+    BootServices,
+};
+
+/// Pages of memory freed when dropped.
+#[must_use]
+pub struct Pages<'a, B: BootServices> {
+    boot_services: &'a B,
+    address: PhysicalAddress,
+    nb_pages: usize,
+}
+
+impl<'a, B: BootServices> Pages<'a, B> {
+    /// Allocate *nb_pages* pages of *memory_type*.
+    ///
+    /// [UEFI Spec Documentation: 7.2.1. EFI_BOOT_SERVICES.AllocatePages()](https://uefi.org/specs/UEFI/2.10/07_Services_Boot_Services.html#efi-boot-services-allocatepages)
+    pub fn allocate(
+        boot_services: &'a B,
+        alloc_type: AllocType,
+        memory_type: MemoryType,
+        nb_pages: usize,
+    ) -> Result<Self, efi::Status> {
+        let address = boot_services.allocate_pages(alloc_type, memory_type, nb_pages)?;
+        Ok(Self { boot_services, address: PhysicalAddress::new(address as u64), nb_pages })
+    }
+
+    /// Allocate *nb_pages* pages of *memory_type* starting on an address aligned on *alignment*.
+    ///
+    /// Extra pages are allocated to find an aligned address, the pages around the aligned range are freed right
+    /// away. *alignment* must be a power of two of at least [`UEFI_PAGE_SIZE`].
+    pub fn allocate_aligned(
+        boot_services: &'a B,
+        memory_type: MemoryType,
+        nb_pages: usize,
+        alignment: usize,
+    ) -> Result<Self, efi::Status> {
+        if !alignment.is_power_of_two() || alignment < UEFI_PAGE_SIZE {
+            return Err(efi::Status::INVALID_PARAMETER);
+        }
+        let extra_pages = alignment / UEFI_PAGE_SIZE - 1;
+        let total_pages = nb_pages.checked_add(extra_pages).ok_or(efi::Status::OUT_OF_RESOURCES)?;
+        let address = boot_services.allocate_pages(AllocType::AnyPage, memory_type, total_pages)?;
+
+        let aligned_address = address.next_multiple_of(alignment);
+        let head_pages = (aligned_address - address) / UEFI_PAGE_SIZE;
+        let tail_pages = extra_pages - head_pages;
+        if head_pages > 0 {
+            let _ = boot_services.free_pages(address, head_pages);
+        }
+        if tail_pages > 0 {
+            let _ = boot_services.free_pages(aligned_address + nb_pages * UEFI_PAGE_SIZE, tail_pages);
+        }
+        Ok(Self { boot_services, address: PhysicalAddress::new(aligned_address as u64), nb_pages })
+    }
+
+    /// Take ownership of pages allocated with [`BootServices::allocate_pages`].
+    ///
+    /// # Safety
+    ///
+    /// *address* and *nb_pages* must describe pages allocated with boot services and not owned by anything else.
+    pub unsafe fn from_raw(boot_services: &'a B, address: PhysicalAddress, nb_pages: usize) -> Self {
+        Self { boot_services, address, nb_pages }
+    }
+
+    /// Give up the ownership of the pages, returning their address and number.
+    pub fn into_raw(self) -> (PhysicalAddress, usize) {
+        let raw = (self.address, self.nb_pages);
+        mem::forget(self);
+        raw
+    }
+
+    /// Give up the ownership of the pages without freeing them, e.g. to hand them to the OS.
+    pub fn leak(self) -> &'a mut [u8] {
+        let (address, nb_pages) = self.into_raw();
+        //SAFETY: The pages are identity mapped and are not owned by anything else since self is consumed.
+        unsafe { slice::from_raw_parts_mut(address.as_ptr(), nb_pages * UEFI_PAGE_SIZE) }
+    }
+
+    /// Free the pages, returning the status of [`BootServices::free_pages`].
+    pub fn free(self) -> Result<(), efi::Status> {
+        let boot_services = self.boot_services;
+        let (address, nb_pages) = self.into_raw();
+        boot_services.free_pages(address.as_u64() as usize, nb_pages)
+    }
+
+    /// Physical address of the first page.
+    pub fn address(&self) -> PhysicalAddress {
+        self.address
+    }
+
+    /// Number of pages owned.
+    pub fn nb_pages(&self) -> usize {
+        self.nb_pages
+    }
+
+    /// Size of the pages in bytes.
+    pub fn size(&self) -> usize {
+        self.nb_pages * UEFI_PAGE_SIZE
+    }
+
+    /// Pointer to the start of the pages.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.address.as_ptr()
+    }
+
+    /// Mutable pointer to the start of the pages.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.address.as_ptr()
+    }
+}
+
+impl<B: BootServices> Drop for Pages<'_, B> {
+    fn drop(&mut self) {
+        let _ = self.boot_services.free_pages(self.address.as_u64() as usize, self.nb_pages);
+    }
+}
+
+impl<B: BootServices> Deref for Pages<'_, B> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        //SAFETY: The pages are identity mapped and owned by self until it is dropped.
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.size()) }
+    }
+}
+
+impl<B: BootServices> DerefMut for Pages<'_, B> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        //SAFETY: The pages are identity mapped and exclusively borrowed through self.
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.size()) }
+    }
+}
+
+impl<B: BootServices> fmt::Debug for Pages<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pages").field("address", &self.address).field("nb_pages", &self.nb_pages).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use alloc::{boxed::Box, vec};
+    use mockall::predicate::eq;
+
+    #[test]
+    fn test_pages_are_freed_on_drop() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_allocate_pages()
+            .withf(|alloc_type, memory_type, nb_pages| {
+                matches!(alloc_type, AllocType::MaxAddress(address) if *address == PhysicalAddress::new(0xFFFF_FFFF))
+                    && *memory_type == MemoryType::ACPI_RECLAIM_MEMORY
+                    && *nb_pages == 2
+            })
+            .times(1)
+            .returning(|_, _, _| Ok(0x200000));
+        boot_services.expect_free_pages().with(eq(0x200000), eq(2)).times(1).returning(|_, _| Ok(()));
+
+        let pages = Pages::allocate(
+            &boot_services,
+            AllocType::MaxAddress(PhysicalAddress::new(0xFFFF_FFFF)),
+            MemoryType::ACPI_RECLAIM_MEMORY,
+            2,
+        )
+        .unwrap();
+        assert_eq!(PhysicalAddress::new(0x200000), pages.address());
+        assert_eq!(2, pages.nb_pages());
+        assert_eq!(0x2000, pages.size());
+        assert_eq!("Pages { address: PhysicalAddress(0x200000), nb_pages: 2 }", format!("{pages:?}"));
+    }
+
+    #[test]
+    fn test_pages_as_slice() {
+        let memory = Box::leak(vec![0_u8; 3 * UEFI_PAGE_SIZE].into_boxed_slice());
+        let address = memory.as_ptr() as usize;
+
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pages().returning(move |_, _, _| Ok(address));
+        boot_services.expect_free_pages().never();
+
+        let mut pages = Pages::allocate(&boot_services, AllocType::AnyPage, MemoryType::LOADER_DATA, 3).unwrap();
+        pages.fill(0xAA);
+        assert_eq!(3 * UEFI_PAGE_SIZE, pages.len());
+        let leaked = pages.leak();
+        assert_eq!(address, leaked.as_ptr() as usize);
+        assert!(memory.iter().all(|b| *b == 0xAA));
+    }
+
+    #[test]
+    fn test_aligned_pages() {
+        let mut boot_services = MockBootServices::new();
+        let mut seq = mockall::Sequence::new();
+        // 2 pages aligned on 2 MiB need 511 extra pages, 0x1FD000 is 3 pages before the aligned address.
+        boot_services
+            .expect_allocate_pages()
+            .withf(|alloc_type, _, nb_pages| matches!(alloc_type, AllocType::AnyPage) && *nb_pages == 513)
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _, _| Ok(0x1FD000));
+        boot_services
+            .expect_free_pages()
+            .with(eq(0x1FD000), eq(3))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        boot_services
+            .expect_free_pages()
+            .with(eq(0x202000), eq(508))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+        boot_services
+            .expect_free_pages()
+            .with(eq(0x200000), eq(2))
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|_, _| Ok(()));
+
+        let pages = Pages::allocate_aligned(&boot_services, MemoryType::BOOT_SERVICES_DATA, 2, 0x200000).unwrap();
+        assert_eq!(PhysicalAddress::new(0x200000), pages.address());
+        assert_eq!(Ok(()), pages.free());
+    }
+
+    #[test]
+    fn test_aligned_pages_invalid_alignment() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_allocate_pages().never();
+
+        for alignment in [0, 0x800, 0x3000] {
+            assert_eq!(
+                efi::Status::INVALID_PARAMETER,
+                Pages::allocate_aligned(&boot_services, MemoryType::BOOT_SERVICES_DATA, 1, alignment).unwrap_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_physical_address() {
+        let address = PhysicalAddress::from(0x1000_u64);
+        assert!(address.is_page_aligned());
+        assert!(!PhysicalAddress::new(0x1800).is_page_aligned());
+        assert_eq!(0x1000_u64, address.into());
+        assert_eq!("0x1000", format!("{address}"));
+    }
+}
@@ -1,7 +1,24 @@
-use core::{ffi::c_void, ops::Deref, ptr::NonNull};
+use alloc::{rc::Rc, vec::Vec};
+use core::{
+    any::Any,
+    cell::{Cell, RefCell},
+    ffi::c_void,
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{self, Deref},
+    ptr::{self, NonNull},
+};
 
 use r_efi::efi;
 
+use crate::{
+    boxed::BootServicesBox,
+    event::{Event, EventType},
+    tpl::Tpl,
+    BootServices,
+};
+
 pub unsafe trait Protocol: Deref<Target = efi::Guid> {
     type Interface;
     fn protocol_guid(&self) -> &'static efi::Guid;
@@ -26,24 +43,621 @@ impl Into<efi::LocateSearchType> for HandleSearchType {
     }
 }
 
-macro_rules! impl_protocol {
-    ($protocol_struct:ident, $protocol_type:ty, $guid:expr) => {
-        pub struct $protocol_struct;
-        unsafe impl Protocol for $protocol_struct {
-            type Interface = $protocol_type;
-            fn protocol_guid(&self) -> &'static efi::Guid {
-                &$guid
+/// Attributes used to open a protocol interface with [`BootServices::open_protocol`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct OpenProtocolAttributes(u32);
+
+impl OpenProtocolAttributes {
+    /// Used in the implementation of [`BootServices::handle_protocol`].
+    /// Since [`BootServices::open_protocol`] performs the same function as [`BootServices::handle_protocol`]
+    /// with additional functionality, [`BootServices::handle_protocol`] can simply call [`BootServices::open_protocol`]
+    /// with this attribute.
+    pub const BY_HANDLE_PROTOCOL: OpenProtocolAttributes =
+        OpenProtocolAttributes(efi::OPEN_PROTOCOL_BY_HANDLE_PROTOCOL);
+
+    /// Used by a driver to get a protocol interface from a handle.
+    /// Care must be taken when using this open mode because the driver that opens a protocol interface in this manner
+    /// will not be informed if the protocol interface is uninstalled or reinstalled.
+    pub const GET_PROTOCOL: OpenProtocolAttributes = OpenProtocolAttributes(efi::OPEN_PROTOCOL_GET_PROTOCOL);
+
+    /// Used by a driver to test for the existence of a protocol interface on a handle.
+    /// The interface returned is null with this attribute.
+    pub const TEST_PROTOCOL: OpenProtocolAttributes = OpenProtocolAttributes(efi::OPEN_PROTOCOL_TEST_PROTOCOL);
+
+    /// Used by bus drivers to show that a protocol interface is being used by one of the child controllers of a bus.
+    pub const BY_CHILD_CONTROLLER: OpenProtocolAttributes =
+        OpenProtocolAttributes(efi::OPEN_PROTOCOL_BY_CHILD_CONTROLLER);
+
+    /// Used by a driver to gain access to a protocol interface.
+    /// When this mode is used, the driver's Stop() function will be called if the protocol interface is reinstalled or
+    /// uninstalled.
+    pub const BY_DRIVER: OpenProtocolAttributes = OpenProtocolAttributes(efi::OPEN_PROTOCOL_BY_DRIVER);
+
+    /// Used by applications to gain exclusive access to a protocol interface.
+    /// If any drivers have the protocol interface opened with an attribute of [`Self::BY_DRIVER`],
+    /// then an attempt will be made to remove them by calling the driver's Stop() function.
+    pub const EXCLUSIVE: OpenProtocolAttributes = OpenProtocolAttributes(efi::OPEN_PROTOCOL_EXCLUSIVE);
+}
+
+impl OpenProtocolAttributes {
+    /// Return true if all the attributes of *other* are set in self.
+    pub const fn contains(self, other: OpenProtocolAttributes) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for OpenProtocolAttributes {
+    type Output = OpenProtocolAttributes;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        OpenProtocolAttributes(self.0 | rhs.0)
+    }
+}
+
+impl ops::BitOrAssign for OpenProtocolAttributes {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl From<OpenProtocolAttributes> for u32 {
+    fn from(attributes: OpenProtocolAttributes) -> Self {
+        attributes.0
+    }
+}
+
+/// Information about an agent that has a protocol interface opened.
+///
+/// This has the same layout as [`efi::OpenProtocolInformationEntry`].
+/// See [`BootServices::open_protocol_information`] for more details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct OpenProtocolInformationEntry {
+    /// Handle of the agent (image) that opened the protocol.
+    pub agent_handle: efi::Handle,
+    /// Handle of the controller that requires the protocol, null if none.
+    pub controller_handle: efi::Handle,
+    /// Attributes used to open the protocol.
+    pub attributes: OpenProtocolAttributes,
+    /// Number of times the protocol has been opened by this agent and controller with these attributes.
+    pub open_count: u32,
+}
+
+const _: () = {
+    assert!(mem::size_of::<OpenProtocolInformationEntry>() == mem::size_of::<efi::OpenProtocolInformationEntry>());
+    assert!(mem::align_of::<OpenProtocolInformationEntry>() == mem::align_of::<efi::OpenProtocolInformationEntry>());
+};
+
+impl OpenProtocolInformationEntry {
+    /// Return true if the protocol is opened by a driver, see [`OpenProtocolAttributes::BY_DRIVER`].
+    pub const fn is_by_driver(&self) -> bool {
+        self.attributes.contains(OpenProtocolAttributes::BY_DRIVER)
+    }
+
+    /// Return true if the protocol is opened by a child controller, see [`OpenProtocolAttributes::BY_CHILD_CONTROLLER`].
+    pub const fn is_by_child_controller(&self) -> bool {
+        self.attributes.contains(OpenProtocolAttributes::BY_CHILD_CONTROLLER)
+    }
+
+    /// Return true if the protocol is opened exclusively, see [`OpenProtocolAttributes::EXCLUSIVE`].
+    pub const fn is_exclusive(&self) -> bool {
+        self.attributes.contains(OpenProtocolAttributes::EXCLUSIVE)
+    }
+}
+
+impl From<efi::OpenProtocolInformationEntry> for OpenProtocolInformationEntry {
+    fn from(entry: efi::OpenProtocolInformationEntry) -> Self {
+        Self {
+            agent_handle: entry.agent_handle,
+            controller_handle: entry.controller_handle,
+            attributes: OpenProtocolAttributes(entry.attributes),
+            open_count: entry.open_count,
+        }
+    }
+}
+
+/// RAII implementation of an opened protocol. When this structure is dropped, the protocol is closed.
+///
+/// The guard derefs to the protocol interface, which can not outlive it.
+/// Mutable access is only given through [`OpenedProtocol::interface_mut`] because other agents may have the same
+/// interface opened.
+#[must_use = "if unused the protocol will immediately be closed"]
+pub struct OpenedProtocol<'a, P: Protocol + 'static, B: BootServices> {
+    boot_services: &'a B,
+    protocol: &'static efi::Guid,
+    interface: NonNull<P::Interface>,
+    handle: efi::Handle,
+    agent_handle: efi::Handle,
+    controller_handle: efi::Handle,
+}
+
+impl<'a, P: Protocol + 'static, B: BootServices> OpenedProtocol<'a, P, B> {
+    /// Opens a protocol and returns an [`OpenedProtocol`] that will close it when dropped.
+    ///
+    /// It is an error to use [`OpenProtocolAttributes::TEST_PROTOCOL`] with this function, since no interface is
+    /// returned, use [`BootServices::open_protocol`] instead.
+    ///
+    /// See [`BootServices::open_protocol`] and [`BootServices::close_protocol`] for more details.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn open(
+        boot_services: &'a B,
+        handle: efi::Handle,
+        protocol: &P,
+        agent_handle: efi::Handle,
+        controller_handle: efi::Handle,
+        attribute: OpenProtocolAttributes,
+    ) -> Result<Self, efi::Status> {
+        let protocol = protocol.protocol_guid();
+        //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
+        let interface = unsafe {
+            boot_services.open_protocol_unchecked(handle, protocol, agent_handle, controller_handle, attribute)?
+        };
+        let interface = match NonNull::new(interface as *mut P::Interface) {
+            Some(interface) => interface,
+            // Protocols without interface (e.g. `()`) are installed with a null pointer.
+            None if mem::size_of::<P::Interface>() == 0 => NonNull::dangling(),
+            None => {
+                let _ = boot_services.close_protocol(handle, protocol, agent_handle, controller_handle);
+                return Err(efi::Status::UNSUPPORTED);
+            }
+        };
+        Ok(Self { boot_services, protocol, interface, handle, agent_handle, controller_handle })
+    }
+
+    /// Handle on which the protocol is opened.
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+
+    /// Handle of the agent that opened the protocol.
+    pub fn agent_handle(&self) -> efi::Handle {
+        self.agent_handle
+    }
+
+    /// Handle of the controller that requires the protocol.
+    pub fn controller_handle(&self) -> efi::Handle {
+        self.controller_handle
+    }
+
+    /// Returns a mutable reference to the protocol interface.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that no other reference to the interface is alive, including
+    /// through another [`OpenedProtocol`] of the same interface. Opening the protocol with
+    /// [`OpenProtocolAttributes::EXCLUSIVE`] or [`OpenProtocolAttributes::BY_DRIVER`] prevents other agents from
+    /// opening it the same way but not with [`OpenProtocolAttributes::GET_PROTOCOL`].
+    pub unsafe fn interface_mut(&mut self) -> &mut P::Interface {
+        self.interface.as_mut()
+    }
+
+    /// Close the protocol and return the status of [`BootServices::close_protocol`].
+    pub fn close(self) -> Result<(), efi::Status> {
+        let status =
+            self.boot_services.close_protocol(self.handle, self.protocol, self.agent_handle, self.controller_handle);
+        mem::forget(self);
+        status
+    }
+}
+
+impl<P: Protocol + 'static, B: BootServices> Drop for OpenedProtocol<'_, P, B> {
+    fn drop(&mut self) {
+        let _ =
+            self.boot_services.close_protocol(self.handle, self.protocol, self.agent_handle, self.controller_handle);
+    }
+}
+
+impl<P: Protocol + 'static, B: BootServices> Deref for OpenedProtocol<'_, P, B> {
+    type Target = P::Interface;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: The interface is valid until the protocol is closed, which happen when self is dropped.
+        unsafe { self.interface.as_ref() }
+    }
+}
+
+impl<P: Protocol + 'static, B: BootServices> fmt::Debug for OpenedProtocol<'_, P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenedProtocol")
+            .field("protocol", self.protocol)
+            .field("interface", &self.interface)
+            .field("handle", &self.handle)
+            .field("agent_handle", &self.agent_handle)
+            .field("controller_handle", &self.controller_handle)
+            .finish()
+    }
+}
+
+/// Notification of protocol installations, built on [`BootServices::register_protocol_notify`].
+///
+/// The notify function is called at the notify tpl once per handle on which the protocol is newly installed.
+/// The notification stops when this structure is dropped.
+#[must_use = "if unused the notification will immediately stop"]
+pub struct ProtocolNotify<'a, P: Protocol + 'static, B: BootServices> {
+    event: Event<'a, B>,
+    registration: Registration,
+    protocol: &'static efi::Guid,
+    _interface: PhantomData<fn(&P::Interface)>,
+}
+
+impl<'a, P, B> ProtocolNotify<'a, P, B>
+where
+    P: Protocol + 'static,
+    P::Interface: 'static,
+    B: BootServices,
+{
+    /// Register *notify_function* to be called for every handle on which *protocol* is installed.
+    ///
+    /// If *include_existing* is true, *notify_function* is also called, before this function returns, for every handle
+    /// that already has the protocol installed. The caller must be running at a tpl lower or equal to *notify_tpl*.
+    ///
+    /// The interface is only borrowed for the duration of the call, use [`OpenedProtocol`] on the handle to keep it or to
+    /// modify it.
+    ///
+    /// See [`BootServices::register_protocol_notify`] and [`BootServices::locate_handle`] for more details.
+    pub fn new<F>(
+        boot_services: &'a B,
+        protocol: &P,
+        notify_tpl: Tpl,
+        include_existing: bool,
+        notify_function: F,
+    ) -> Result<Self, efi::Status>
+    where
+        F: FnMut(efi::Handle, &P::Interface) + 'static,
+    {
+        let protocol = protocol.protocol_guid();
+        let shared_registration = Rc::new(Cell::new(None));
+        let notify_function = Rc::new(RefCell::new(notify_function));
+
+        let event = {
+            let registration = shared_registration.clone();
+            let notify_function = notify_function.clone();
+            Event::new_with_closure(boot_services, EventType::NOTIFY_SIGNAL, notify_tpl, move |event| {
+                if let Some(registration) = registration.get() {
+                    let mut notify_function = notify_function.borrow_mut();
+                    Self::notify_new_handles(event.boot_services(), protocol, registration, &mut *notify_function);
+                }
+            })?
+        };
+
+        // The event notify function can not run while the existing handles are processed.
+        let old_tpl = boot_services.raise_tpl(notify_tpl);
+        let registration = boot_services.register_protocol_notify(protocol, event.as_raw());
+        if let Ok(registration) = registration {
+            shared_registration.set(Some(registration));
+            if include_existing {
+                // Handles installed since the registration are part of the existing handles.
+                while boot_services.locate_handle(HandleSearchType::ByRegisterNotify(registration)).is_ok() {}
+                if let Ok(handles) = boot_services.locate_handle(HandleSearchType::ByProtocol(protocol)) {
+                    let mut notify_function = notify_function.borrow_mut();
+                    for &handle in handles.iter() {
+                        Self::notify_handle(boot_services, protocol, handle, &mut *notify_function);
+                    }
+                }
+            }
+        }
+        boot_services.restore_tpl(old_tpl);
+
+        Ok(Self { event, registration: registration?, protocol, _interface: PhantomData })
+    }
+
+    /// Return the registration key used to locate the newly installed handles.
+    pub fn registration(&self) -> Registration {
+        self.registration
+    }
+
+    /// Return the event signaled when the protocol is installed.
+    pub fn event(&self) -> &Event<'a, B> {
+        &self.event
+    }
+
+    fn notify_new_handles(
+        boot_services: &B,
+        protocol: &'static efi::Guid,
+        registration: Registration,
+        notify_function: &mut impl FnMut(efi::Handle, &P::Interface),
+    ) {
+        // Only one handle is returned at a time when searching by register notify.
+        while let Ok(handles) = boot_services.locate_handle(HandleSearchType::ByRegisterNotify(registration)) {
+            for &handle in handles.iter() {
+                Self::notify_handle(boot_services, protocol, handle, notify_function);
             }
         }
+    }
+
+    fn notify_handle(
+        boot_services: &B,
+        protocol: &'static efi::Guid,
+        handle: efi::Handle,
+        notify_function: &mut impl FnMut(efi::Handle, &P::Interface),
+    ) {
+        //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
+        let Ok(interface) = (unsafe { boot_services.handle_protocol_unchecked(handle, protocol) }) else {
+            // The protocol has been uninstalled since.
+            return;
+        };
+        let interface = match NonNull::new(interface as *mut P::Interface) {
+            Some(interface) => interface,
+            // Protocols without interface (e.g. `()`) are installed with a null pointer.
+            None if mem::size_of::<P::Interface>() == 0 => NonNull::dangling(),
+            None => return,
+        };
+        //SAFETY: The interface is valid as long as the protocol stay installed.
+        notify_function(handle, unsafe { interface.as_ref() });
+    }
+}
+
+impl<P: Protocol + 'static, B: BootServices> fmt::Debug for ProtocolNotify<'_, P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtocolNotify")
+            .field("protocol", self.protocol)
+            .field("event", &self.event)
+            .field("registration", &self.registration)
+            .finish()
+    }
+}
+
+/// Find the handles on which *protocol* is installed, iterating over `(handle, interface)` pairs.
+///
+/// The handles are located with [`BootServices::locate_handle_buffer`] and the interfaces are retrieved with
+/// [`BootServices::handle_protocol_unchecked`], or [`BootServices::open_protocol_unchecked`] with
+/// [`OpenProtocolAttributes::GET_PROTOCOL`] when [`ProtocolInstances::get_protocol`] is used.
+///
+/// ```ignore
+/// for (handle, block_io) in find_protocol_instances(&boot_services, &BlockIo)? {
+///     // ...
+/// }
+/// ```
+pub fn find_protocol_instances<'a, P: Protocol + 'static, B: BootServices>(
+    boot_services: &'a B,
+    protocol: &P,
+) -> Result<ProtocolInstances<'a, P, B>, efi::Status> {
+    let handles = match boot_services.locate_handle_buffer(HandleSearchType::ByProtocol(protocol.protocol_guid())) {
+        Ok(handles) => Some(handles),
+        Err(efi::Status::NOT_FOUND) => None,
+        Err(status) => return Err(status),
+    };
+    Ok(ProtocolInstances {
+        boot_services,
+        protocol: protocol.protocol_guid(),
+        handles,
+        index: 0,
+        get_protocol: None,
+        _protocol: PhantomData,
+    })
+}
+
+/// Return the first handle on which *protocol* is installed with its interface, [`efi::Status::NOT_FOUND`] if none.
+pub fn first_protocol_instance<'a, P: Protocol + 'static, B: BootServices>(
+    boot_services: &'a B,
+    protocol: &P,
+) -> Result<(efi::Handle, &'a P::Interface), efi::Status> {
+    find_protocol_instances(boot_services, protocol)?.next().ok_or(efi::Status::NOT_FOUND)
+}
+
+/// Iterator returned by [`find_protocol_instances`].
+///
+/// Handles on which the protocol has been uninstalled since they have been located are skipped.
+pub struct ProtocolInstances<'a, P: Protocol + 'static, B: BootServices> {
+    boot_services: &'a B,
+    protocol: &'static efi::Guid,
+    handles: Option<BootServicesBox<'a, [efi::Handle], B>>,
+    index: usize,
+    get_protocol: Option<(efi::Handle, efi::Handle)>,
+    _protocol: PhantomData<P>,
+}
+
+impl<P: Protocol + 'static, B: BootServices> ProtocolInstances<'_, P, B> {
+    /// Retrieve the interfaces with [`BootServices::open_protocol_unchecked`] using
+    /// [`OpenProtocolAttributes::GET_PROTOCOL`] on behalf of *agent_handle* and *controller_handle*.
+    pub fn get_protocol(mut self, agent_handle: efi::Handle, controller_handle: efi::Handle) -> Self {
+        self.get_protocol = Some((agent_handle, controller_handle));
+        self
+    }
+
+    /// Handles located, including the ones already iterated.
+    pub fn handles(&self) -> &[efi::Handle] {
+        self.handles.as_deref().unwrap_or_default()
+    }
+}
+
+impl<'a, P: Protocol + 'static, B: BootServices> Iterator for ProtocolInstances<'a, P, B>
+where
+    P::Interface: 'a,
+{
+    type Item = (efi::Handle, &'a P::Interface);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&handle) = self.handles().get(self.index) {
+            self.index += 1;
+            //SAFETY: The generic Protocol ensure that the interface is the right type for the specified protocol.
+            let interface = unsafe {
+                match self.get_protocol {
+                    Some((agent_handle, controller_handle)) => self.boot_services.open_protocol_unchecked(
+                        handle,
+                        self.protocol,
+                        agent_handle,
+                        controller_handle,
+                        OpenProtocolAttributes::GET_PROTOCOL,
+                    ),
+                    None => self.boot_services.handle_protocol_unchecked(handle, self.protocol),
+                }
+            };
+            let interface = match interface.map(|interface| NonNull::new(interface as *mut P::Interface)) {
+                Ok(Some(interface)) => interface,
+                // Protocols without interface (e.g. `()`) are installed with a null pointer.
+                Ok(None) if mem::size_of::<P::Interface>() == 0 => NonNull::dangling(),
+                _ => continue,
+            };
+            //SAFETY: The interface stays valid as long as the protocol is installed.
+            return Some((handle, unsafe { interface.as_ref() }));
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.handles().len() - self.index))
+    }
+}
+
+impl<P: Protocol + 'static, B: BootServices> fmt::Debug for ProtocolInstances<'_, P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProtocolInstances")
+            .field("protocol", self.protocol)
+            .field("handles", &self.handles())
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+/// Group of protocol interfaces that can be installed or uninstalled together.
+///
+/// This is implemented for tuples of `(&P, &'static mut P::Interface)` pairs, for example:
+/// ```ignore
+/// boot_services.install_multiple_protocol_interfaces(None, ((&DevicePath, device_path), (&LoadFile2, load_file2)))
+/// ```
+///
+/// # Safety
+///
+/// Every interface pointer returned by [`ProtocolInterfaces::into_raw_interfaces`] must be null or adhere to the structure
+/// associated with the protocol GUID it is paired with.
+pub unsafe trait ProtocolInterfaces {
+    /// Return the protocol GUID and interface pointer pairs in order.
+    fn into_raw_interfaces(self) -> Vec<(&'static efi::Guid, *mut c_void)>;
+}
+
+/// Return the pointer to give to the firmware for an interface, null if the interface is `()`.
+pub(crate) fn interface_ptr<I: Any>(interface: &'static mut I) -> *mut c_void {
+    match (interface as &dyn Any).downcast_ref::<()>() {
+        Some(()) => ptr::null_mut(),
+        None => interface as *mut _ as *mut c_void,
+    }
+}
+
+macro_rules! impl_protocol_interfaces {
+    ($(($protocol:ident, $interface:ident, $idx:tt)),+) => {
+        unsafe impl<$($protocol, $interface),+> ProtocolInterfaces for ($((&'static $protocol, &'static mut $interface),)+)
+        where
+            $($protocol: Protocol<Interface = $interface> + 'static, $interface: Any + 'static),+
+        {
+            fn into_raw_interfaces(self) -> Vec<(&'static efi::Guid, *mut c_void)> {
+                alloc::vec![$((self.$idx.0.protocol_guid(), interface_ptr(self.$idx.1))),+]
+            }
+        }
+    };
+}
+
+impl_protocol_interfaces!((P0, I0, 0));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1), (P2, I2, 2));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1), (P2, I2, 2), (P3, I3, 3));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1), (P2, I2, 2), (P3, I3, 3), (P4, I4, 4));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1), (P2, I2, 2), (P3, I3, 3), (P4, I4, 4), (P5, I5, 5));
+impl_protocol_interfaces!((P0, I0, 0), (P1, I1, 1), (P2, I2, 2), (P3, I3, 3), (P4, I4, 4), (P5, I5, 5), (P6, I6, 6));
+impl_protocol_interfaces!(
+    (P0, I0, 0),
+    (P1, I1, 1),
+    (P2, I2, 2),
+    (P3, I3, 3),
+    (P4, I4, 4),
+    (P5, I5, 5),
+    (P6, I6, 6),
+    (P7, I7, 7)
+);
+
+/// Define a protocol usable with the [`BootServices`] protocol handler services.
+///
+/// This generates a unit struct implementing [`Protocol`] and `Deref<Target = efi::Guid>`, with a `GUID` associated
+/// constant. The GUID is either a string in the `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` format or an `efi::Guid`
+/// constant expression.
+///
+/// ```ignore
+/// define_protocol! {
+///     /// Advanced logger protocol.
+///     pub struct AdvancedLogger(AdvancedLoggerInterface) = "434F695C-EF26-4A12-9EBA-DDEF0097497C";
+/// }
+///
+/// let interface = boot_services.locate_protocol(&AdvancedLogger, None)?;
+/// ```
+#[macro_export]
+macro_rules! define_protocol {
+    ($(#[$attr:meta])* $vis:vis struct $protocol_struct:ident($interface:ty) = $guid:literal $(;)?) => {
+        $crate::define_protocol! {
+            $(#[$attr])*
+            $vis struct $protocol_struct($interface) = $crate::protocol_handler::__private::guid_from_str($guid);
+        }
+    };
+    ($(#[$attr:meta])* $vis:vis struct $protocol_struct:ident($interface:ty) = $guid:expr $(;)?) => {
+        $(#[$attr])*
+        $vis struct $protocol_struct;
+
+        impl $protocol_struct {
+            pub const GUID: $crate::protocol_handler::__private::Guid = $guid;
+        }
+
+        unsafe impl $crate::protocol_handler::Protocol for $protocol_struct {
+            type Interface = $interface;
+            fn protocol_guid(&self) -> &'static $crate::protocol_handler::__private::Guid {
+                &Self::GUID
+            }
+        }
+
         impl core::ops::Deref for $protocol_struct {
-            type Target = r_efi::efi::Guid;
+            type Target = $crate::protocol_handler::__private::Guid;
             fn deref(&self) -> &Self::Target {
-                &self.protocol_guid()
+                &Self::GUID
             }
         }
     };
 }
 
+#[doc(hidden)]
+pub mod __private {
+    pub use r_efi::efi::Guid;
+
+    /// Parse a GUID in the `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` format, panic if the format is invalid.
+    pub const fn guid_from_str(guid: &str) -> Guid {
+        let guid = guid.as_bytes();
+        assert!(guid.len() == 36, "GUID string must be in the XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX format.");
+        let mut bytes = [0_u8; 16];
+        let (mut i, mut j) = (0, 0);
+        while j < guid.len() {
+            if matches!(j, 8 | 13 | 18 | 23) {
+                assert!(guid[j] == b'-', "GUID string must be in the XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX format.");
+                j += 1;
+                continue;
+            }
+            bytes[i] = hex_digit(guid[j]) << 4 | hex_digit(guid[j + 1]);
+            i += 1;
+            j += 2;
+        }
+        Guid::from_fields(
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            u16::from_be_bytes([bytes[4], bytes[5]]),
+            u16::from_be_bytes([bytes[6], bytes[7]]),
+            bytes[8],
+            bytes[9],
+            &[bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15]],
+        )
+    }
+
+    const fn hex_digit(digit: u8) -> u8 {
+        match digit {
+            b'0'..=b'9' => digit - b'0',
+            b'a'..=b'f' => digit - b'a' + 10,
+            b'A'..=b'F' => digit - b'A' + 10,
+            _ => panic!("GUID string contains an invalid hexadecimal digit."),
+        }
+    }
+}
+
+macro_rules! impl_protocol {
+    ($protocol_struct:ident, $protocol_type:ty, $guid:expr) => {
+        crate::define_protocol!(pub struct $protocol_struct($protocol_type) = $guid);
+    };
+}
+
 macro_rules! impl_r_efi_protocol {
     ($protocol_struct:ident, $protocol:ident) => {
         impl_protocol! {
@@ -54,6 +668,20 @@ macro_rules! impl_r_efi_protocol {
     };
 }
 
+macro_rules! impl_r_efi_service_binding {
+    ($protocol_struct:ident, $protocol:ident, $child_struct:ident) => {
+        impl_protocol! {
+          $protocol_struct,
+          r_efi::efi::protocols::service_binding::Protocol,
+          r_efi::efi::protocols::$protocol::SERVICE_BINDING_PROTOCOL_GUID
+        }
+        impl crate::service_binding::ServiceBindingProtocol for $protocol_struct {
+            type Child = $child_struct;
+            const CHILD: Self::Child = $child_struct;
+        }
+    };
+}
+
 impl_r_efi_protocol!(AbsolutePointer, absolute_pointer);
 impl_r_efi_protocol!(BlockIo, block_io);
 impl_r_efi_protocol!(BusSpecificDriverOverride, bus_specific_driver_override);
@@ -62,35 +690,39 @@ impl_r_efi_protocol!(DebugPort, debugport);
 impl_r_efi_protocol!(Decompress, decompress);
 impl_r_efi_protocol!(DevicePath, device_path);
 impl_r_efi_protocol!(DevicePathFromText, device_path_from_text);
+impl_r_efi_protocol!(DevicePathToText, device_path_to_text);
 impl_r_efi_protocol!(DevicePathUtilities, device_path_utilities);
 impl_r_efi_protocol!(DiskIo, disk_io);
 impl_r_efi_protocol!(DiskIo2, disk_io2);
 impl_r_efi_protocol!(DriverBinding, driver_binding);
 impl_r_efi_protocol!(DriverDiagnostic2, driver_diagnostics2);
 impl_r_efi_protocol!(DriverFamilyOverride, driver_family_override);
-// protocol file ???;
+// The file protocol has no GUID, it is opened with the simple file system protocol.
 impl_r_efi_protocol!(GraphicOutput, graphics_output);
 impl_r_efi_protocol!(HiiDatabase, hii_database);
 impl_r_efi_protocol!(HiiFont, hii_font);
 impl_r_efi_protocol!(HiiFontEx, hii_font_ex);
-// protocol hii_package_list ???;
+impl_protocol!(HiiPackageList, efi::hii::PackageListHeader, efi::protocols::hii_package_list::PROTOCOL_GUID);
 impl_r_efi_protocol!(HiiString, hii_string);
 impl_r_efi_protocol!(Ip4, ip4);
+impl_r_efi_service_binding!(Ip4ServiceBinding, ip4, Ip4);
 impl_r_efi_protocol!(Ip6, ip6);
+impl_r_efi_service_binding!(Ip6ServiceBinding, ip6, Ip6);
 impl_r_efi_protocol!(LoadFile, load_file);
 impl_r_efi_protocol!(LoadFile2, load_file2);
 impl_r_efi_protocol!(LoadedImage, loaded_image);
 impl_protocol!(
     LoadedImageDevicePath,
-    efi::protocols::loaded_image::Protocol,
+    efi::protocols::device_path::Protocol,
     efi::protocols::loaded_image_device_path::PROTOCOL_GUID
 );
 impl_r_efi_protocol!(ManagedNetwork, managed_network);
+impl_r_efi_service_binding!(ManagedNetworkServiceBinding, managed_network, ManagedNetwork);
+impl_r_efi_protocol!(MemoryAttribute, memory_attribute);
 impl_r_efi_protocol!(MpService, mp_services);
 impl_r_efi_protocol!(PciIo, pci_io);
 impl_r_efi_protocol!(PlatformDriverOverride, platform_driver_override);
 impl_r_efi_protocol!(Rng, rng);
-// protocol service_binding ???
 impl_r_efi_protocol!(Shell, shell);
 impl_r_efi_protocol!(ShellDynamicCommand, shell_dynamic_command);
 impl_r_efi_protocol!(ShellParameters, shell_parameters);
@@ -100,7 +732,313 @@ impl_r_efi_protocol!(SimpleTextInput, simple_text_input);
 impl_r_efi_protocol!(SimpleTextInputEx, simple_text_input_ex);
 impl_r_efi_protocol!(SimpleTextOutput, simple_text_output);
 impl_r_efi_protocol!(Tcp4, tcp4);
+impl_r_efi_service_binding!(Tcp4ServiceBinding, tcp4, Tcp4);
 impl_r_efi_protocol!(Tcp6, tcp6);
+impl_r_efi_service_binding!(Tcp6ServiceBinding, tcp6, Tcp6);
 impl_r_efi_protocol!(Timerstamp, timestamp);
 impl_r_efi_protocol!(Udp4, udp4);
+impl_r_efi_service_binding!(Udp4ServiceBinding, udp4, Udp4);
 impl_r_efi_protocol!(Udp6, udp6);
+impl_r_efi_service_binding!(Udp6ServiceBinding, udp6, Udp6);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{boxed::test::boxed_slice, service_binding::ServiceBindingProtocol, MockBootServices};
+    use alloc::boxed::Box;
+    use mockall::predicate::eq;
+    use std::sync::Mutex;
+
+    fn device_path() -> &'static mut efi::protocols::device_path::Protocol {
+        Box::leak(Box::new(efi::protocols::device_path::Protocol {
+            r#type: efi::protocols::device_path::TYPE_END,
+            sub_type: efi::protocols::device_path::End::SUBTYPE_ENTIRE,
+            length: [4, 0],
+        }))
+    }
+
+    #[test]
+    fn test_opened_protocol_is_closed_on_drop() {
+        let mut boot_services = MockBootServices::new();
+        let interface = device_path() as *mut _ as usize;
+        boot_services
+            .expect_open_protocol_unchecked()
+            .withf(|handle, protocol, agent_handle, controller_handle, attribute| {
+                *handle as usize == 1
+                    && protocol == &efi::protocols::device_path::PROTOCOL_GUID
+                    && *agent_handle as usize == 2
+                    && *controller_handle as usize == 3
+                    && *attribute == OpenProtocolAttributes::BY_DRIVER
+            })
+            .returning(move |_, _, _, _, _| Ok(interface as *mut c_void));
+        boot_services
+            .expect_close_protocol()
+            .withf(|handle, protocol, agent_handle, controller_handle| {
+                *handle as usize == 1
+                    && protocol == &efi::protocols::device_path::PROTOCOL_GUID
+                    && *agent_handle as usize == 2
+                    && *controller_handle as usize == 3
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        let mut opened = OpenedProtocol::open(
+            &boot_services,
+            1_usize as efi::Handle,
+            &DevicePath,
+            2_usize as efi::Handle,
+            3_usize as efi::Handle,
+            OpenProtocolAttributes::BY_DRIVER,
+        )
+        .unwrap();
+        assert_eq!(efi::protocols::device_path::TYPE_END, opened.r#type);
+        //SAFETY: The protocol is opened by driver and no other reference to the interface exist.
+        unsafe { opened.interface_mut().length = [4, 0] };
+        assert_eq!([4, 0], opened.length);
+        assert_eq!(1, opened.handle() as usize);
+        drop(opened);
+        boot_services.checkpoint();
+    }
+
+    #[test]
+    fn test_opened_protocol_close_return_status() {
+        let mut boot_services = MockBootServices::new();
+        let interface = device_path() as *mut _ as usize;
+        boot_services.expect_open_protocol_unchecked().returning(move |_, _, _, _, _| Ok(interface as *mut c_void));
+        boot_services.expect_close_protocol().times(1).returning(|_, _, _, _| Err(efi::Status::NOT_FOUND));
+
+        let opened = OpenedProtocol::open(
+            &boot_services,
+            1_usize as efi::Handle,
+            &DevicePath,
+            2_usize as efi::Handle,
+            ptr::null_mut(),
+            OpenProtocolAttributes::GET_PROTOCOL,
+        )
+        .unwrap();
+        assert_eq!(Err(efi::Status::NOT_FOUND), opened.close());
+    }
+
+    #[test]
+    fn test_opened_protocol_with_null_interface() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_open_protocol_unchecked().returning(|_, _, _, _, _| Ok(ptr::null_mut()));
+        boot_services.expect_close_protocol().times(1).returning(|_, _, _, _| Ok(()));
+
+        let opened = OpenedProtocol::open(
+            &boot_services,
+            1_usize as efi::Handle,
+            &DevicePath,
+            2_usize as efi::Handle,
+            ptr::null_mut(),
+            OpenProtocolAttributes::TEST_PROTOCOL,
+        );
+        assert!(matches!(opened, Err(efi::Status::UNSUPPORTED)));
+    }
+
+    #[test]
+    fn test_open_protocol_attributes() {
+        let attributes = OpenProtocolAttributes::BY_DRIVER | OpenProtocolAttributes::EXCLUSIVE;
+        assert!(attributes.contains(OpenProtocolAttributes::BY_DRIVER));
+        assert!(attributes.contains(OpenProtocolAttributes::EXCLUSIVE));
+        assert!(!attributes.contains(OpenProtocolAttributes::GET_PROTOCOL));
+        let attributes: u32 = attributes.into();
+        assert_eq!(efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE, attributes);
+    }
+
+    #[test]
+    fn test_open_protocol_information_entry_from_efi() {
+        let entry = OpenProtocolInformationEntry::from(efi::OpenProtocolInformationEntry {
+            agent_handle: 1_usize as efi::Handle,
+            controller_handle: 2_usize as efi::Handle,
+            attributes: efi::OPEN_PROTOCOL_BY_DRIVER | efi::OPEN_PROTOCOL_EXCLUSIVE,
+            open_count: 3,
+        });
+        assert_eq!(1, entry.agent_handle as usize);
+        assert_eq!(2, entry.controller_handle as usize);
+        assert_eq!(OpenProtocolAttributes::BY_DRIVER | OpenProtocolAttributes::EXCLUSIVE, entry.attributes);
+        assert_eq!(3, entry.open_count);
+        assert!(entry.is_by_driver());
+        assert!(entry.is_exclusive());
+        assert!(!entry.is_by_child_controller());
+    }
+
+    fn handles(handles: &[usize]) -> BootServicesBox<'static, [efi::Handle], MockBootServices> {
+        boxed_slice(handles.iter().map(|h| *h as efi::Handle).collect())
+    }
+
+    #[test]
+    fn test_find_protocol_instances() {
+        static INTERFACES: [u64; 3] = [10, 20, 30];
+
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_locate_handle_buffer()
+            .withf(|search_type| {
+                matches!(search_type, HandleSearchType::ByProtocol(guid) if **guid == efi::protocols::block_io::PROTOCOL_GUID)
+            })
+            .returning(|_| Ok(handles(&[1, 2, 3])));
+        // The protocol has been uninstalled from the second handle.
+        boot_services.expect_handle_protocol_unchecked().returning(|handle, _| match handle as usize {
+            2 => Err(efi::Status::UNSUPPORTED),
+            h => Ok(ptr::addr_of!(INTERFACES[h - 1]) as *mut c_void),
+        });
+
+        let instances = find_protocol_instances(&boot_services, &BlockIo).unwrap();
+        assert_eq!(3, instances.handles().len());
+        let instances = instances
+            .map(|(handle, interface)| (handle as usize, interface as *const _ as *const u64))
+            .map(|(handle, interface)| (handle, unsafe { *interface }))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(1, 10), (3, 30)], instances);
+
+        let (handle, _) = first_protocol_instance(&boot_services, &BlockIo).unwrap();
+        assert_eq!(1, handle as usize);
+    }
+
+    #[test]
+    fn test_find_protocol_instances_not_found() {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_locate_handle_buffer().returning(|_| Err(efi::Status::NOT_FOUND));
+        boot_services.expect_handle_protocol_unchecked().never();
+
+        assert_eq!(0, find_protocol_instances(&boot_services, &BlockIo).unwrap().count());
+        assert_eq!(efi::Status::NOT_FOUND, first_protocol_instance(&boot_services, &BlockIo).err().unwrap());
+    }
+
+    #[test]
+    fn test_find_protocol_instances_with_get_protocol() {
+        static INTERFACE: u64 = 42;
+
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_locate_handle_buffer().returning(|_| Ok(handles(&[1])));
+        boot_services.expect_handle_protocol_unchecked().never();
+        boot_services
+            .expect_open_protocol_unchecked()
+            .withf(|handle, protocol, agent_handle, controller_handle, attributes| {
+                *handle as usize == 1
+                    && *protocol == efi::protocols::block_io::PROTOCOL_GUID
+                    && *agent_handle as usize == 0x10
+                    && controller_handle.is_null()
+                    && *attributes == OpenProtocolAttributes::GET_PROTOCOL
+            })
+            .returning(|_, _, _, _, _| Ok(ptr::addr_of!(INTERFACE) as *mut c_void));
+
+        let mut instances = find_protocol_instances(&boot_services, &BlockIo)
+            .unwrap()
+            .get_protocol(0x10 as efi::Handle, ptr::null_mut());
+        let (handle, interface) = instances.next().unwrap();
+        assert_eq!(1, handle as usize);
+        assert_eq!(ptr::addr_of!(INTERFACE) as usize, interface as *const _ as usize);
+        assert!(instances.next().is_none());
+    }
+
+    crate::define_protocol! {
+        /// Protocol defined outside of r-efi.
+        pub struct AdvancedLogger(u64) = "434F695C-EF26-4A12-9EBA-DDEF0097497C";
+    }
+
+    crate::define_protocol!(struct FromExpr(()) = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6, 7, 8, 9, 10, 11]));
+
+    #[test]
+    fn test_define_protocol() {
+        let expected_guid =
+            efi::Guid::from_fields(0x434f695c, 0xef26, 0x4a12, 0x9e, 0xba, &[0xdd, 0xef, 0x00, 0x97, 0x49, 0x7c]);
+        assert_eq!(expected_guid, AdvancedLogger::GUID);
+        assert_eq!(&expected_guid, AdvancedLogger.protocol_guid());
+        assert_eq!(expected_guid, *AdvancedLogger);
+        assert_eq!(__private::guid_from_str("434f695c-ef26-4a12-9eba-ddef0097497c"), AdvancedLogger::GUID);
+
+        assert_eq!(efi::Guid::from_fields(1, 2, 3, 4, 5, &[6, 7, 8, 9, 10, 11]), *FromExpr);
+        assert_eq!(&efi::protocols::block_io::PROTOCOL_GUID, BlockIo.protocol_guid());
+        assert_eq!(efi::protocols::block_io::PROTOCOL_GUID, BlockIo::GUID);
+    }
+
+    #[test]
+    fn test_protocol_table() {
+        fn interface<P: Protocol<Interface = I>, I>(_protocol: &P) -> core::any::TypeId
+        where
+            I: 'static,
+        {
+            core::any::TypeId::of::<I>()
+        }
+
+        assert_eq!(core::any::TypeId::of::<efi::protocols::device_path::Protocol>(), interface(&LoadedImageDevicePath));
+        assert_eq!(efi::protocols::loaded_image_device_path::PROTOCOL_GUID, *LoadedImageDevicePath);
+        assert_eq!(core::any::TypeId::of::<efi::hii::PackageListHeader>(), interface(&HiiPackageList));
+        assert_eq!(efi::protocols::tcp4::SERVICE_BINDING_PROTOCOL_GUID, *Tcp4ServiceBinding);
+        assert_eq!(efi::protocols::tcp4::PROTOCOL_GUID, *<Tcp4ServiceBinding as ServiceBindingProtocol>::CHILD);
+    }
+
+    #[test]
+    #[should_panic = "GUID string contains an invalid hexadecimal digit."]
+    fn test_guid_from_str_invalid_digit() {
+        __private::guid_from_str("434F695C-EF26-4A12-9EBA-DDEF0097497G");
+    }
+
+    #[test]
+    #[should_panic = "GUID string must be in the XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX format."]
+    fn test_guid_from_str_invalid_format() {
+        __private::guid_from_str("434F695CEF26-4A12-9EBA-DDEF0097497C0");
+    }
+
+    #[test]
+    fn test_protocol_notify() {
+        static NOTIFY: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+        // Handle on which the protocol is installed before the registration, then after.
+        static NEW_HANDLES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_raise_tpl().with(eq(Tpl::CALLBACK)).return_const(Tpl::APPLICATION);
+        boot_services.expect_restore_tpl().with(eq(Tpl::APPLICATION)).return_const(());
+        boot_services
+            .expect_create_event_unchecked::<c_void>()
+            .withf(|event_type, notify_tpl, _, _| {
+                *event_type == EventType::NOTIFY_SIGNAL && *notify_tpl == Tpl::CALLBACK
+            })
+            .returning(|_, _, notify_function, notify_context| {
+                *NOTIFY.lock().unwrap() = Some((notify_function.unwrap() as usize, notify_context as usize));
+                Ok(1_usize as efi::Event)
+            });
+        boot_services
+            .expect_register_protocol_notify()
+            .withf(|protocol, event| protocol == &efi::protocols::device_path::PROTOCOL_GUID && *event as usize == 1)
+            .times(1)
+            .returning(|_, _| Ok(NonNull::dangling()));
+        boot_services.expect_locate_handle().returning(move |search_type| match search_type {
+            HandleSearchType::ByProtocol(_) => Ok(handles(&[10])),
+            HandleSearchType::ByRegisterNotify(_) => match NEW_HANDLES.lock().unwrap().pop() {
+                Some(handle) => Ok(handles(&[handle])),
+                None => Err(efi::Status::NOT_FOUND),
+            },
+            HandleSearchType::AllHandle => panic!("unexpected search type"),
+        });
+        let interface = device_path() as *mut _ as usize;
+        boot_services.expect_handle_protocol_unchecked().returning(move |handle, _| match handle as usize {
+            13 => Err(efi::Status::UNSUPPORTED),
+            _ => Ok(interface as *mut c_void),
+        });
+        boot_services.expect_close_event().times(1).returning(|_| Ok(()));
+
+        let notified = Rc::new(RefCell::new(Vec::new()));
+        let protocol_notify = {
+            let notified = notified.clone();
+            ProtocolNotify::new(&boot_services, &DevicePath, Tpl::CALLBACK, true, move |handle, interface| {
+                assert_eq!(efi::protocols::device_path::TYPE_END, interface.r#type);
+                notified.borrow_mut().push(handle as usize);
+            })
+            .unwrap()
+        };
+        assert_eq!(vec![10], *notified.borrow());
+
+        // Simulate the firmware signaling the event after 3 installations, one being already uninstalled.
+        NEW_HANDLES.lock().unwrap().extend([12, 13, 11]);
+        let (notify_function, notify_context) = NOTIFY.lock().unwrap().unwrap();
+        let notify_function: extern "efiapi" fn(efi::Event, *mut c_void) = unsafe { mem::transmute(notify_function) };
+        notify_function(protocol_notify.event().as_raw(), notify_context as *mut c_void);
+        assert_eq!(vec![10, 11, 12], *notified.borrow());
+
+        drop(protocol_notify);
+        boot_services.checkpoint();
+    }
+}
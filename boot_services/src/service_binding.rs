@@ -0,0 +1,211 @@
+//! Service binding protocols, used to create and destroy child handles producing a protocol.
+//!
+//! ```ignore
+//! let tcp4_service_binding = ServiceBinding::new(&boot_services, nic_handle, &Tcp4ServiceBinding)?;
+//! let tcp4 = tcp4_service_binding.create_child()?;
+//! // `tcp4` dereferences to the Tcp4 protocol interface, the child is destroyed when dropped.
+//! ```
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+use r_efi::efi;
+
+use crate::{protocol_handler::Protocol, BootServices};
+
+/// Service binding protocol paired with the protocol produced on the child handles it creates.
+pub trait ServiceBindingProtocol: Protocol<Interface = efi::protocols::service_binding::Protocol> + 'static {
+    /// Protocol installed on the child handles.
+    type Child: Protocol<Interface: 'static> + 'static;
+    /// Instance of the child protocol, used to get its interface on the child handles.
+    const CHILD: Self::Child;
+}
+
+/// Service binding protocol instance installed on a handle.
+pub struct ServiceBinding<'a, P: ServiceBindingProtocol, B: BootServices> {
+    boot_services: &'a B,
+    handle: efi::Handle,
+    interface: *mut efi::protocols::service_binding::Protocol,
+    _protocol: PhantomData<P>,
+}
+
+impl<'a, P: ServiceBindingProtocol, B: BootServices> ServiceBinding<'a, P, B> {
+    /// Get the service binding *protocol* installed on *handle*.
+    pub fn new(boot_services: &'a B, handle: efi::Handle, protocol: &P) -> Result<Self, efi::Status> {
+        let interface = boot_services.handle_protocol(handle, protocol)?;
+        Ok(Self { boot_services, handle, interface, _protocol: PhantomData })
+    }
+
+    /// Handle on which the service binding protocol is installed.
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+
+    /// Create a child handle producing the child protocol, or add it to *handle* if any.
+    ///
+    /// [UEFI Spec Documentation: 11.6.1. EFI_SERVICE_BINDING_PROTOCOL.CreateChild()](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-service-binding-protocol-createchild)
+    pub fn create_child_handle(&self, handle: Option<efi::Handle>) -> Result<efi::Handle, efi::Status> {
+        let mut handle = handle.unwrap_or(ptr::null_mut());
+        match unsafe { ((*self.interface).create_child)(self.interface, ptr::addr_of_mut!(handle)) } {
+            s if s.is_error() => Err(s),
+            _ => Ok(handle),
+        }
+    }
+
+    /// Destroy a child handle created with [`Self::create_child_handle`].
+    ///
+    /// [UEFI Spec Documentation: 11.6.2. EFI_SERVICE_BINDING_PROTOCOL.DestroyChild()](https://uefi.org/specs/UEFI/2.10/11_Protocols_UEFI_Driver_Model.html#efi-service-binding-protocol-destroychild)
+    pub fn destroy_child_handle(&self, handle: efi::Handle) -> Result<(), efi::Status> {
+        match unsafe { ((*self.interface).destroy_child)(self.interface, handle) } {
+            s if s.is_error() => Err(s),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create a child handle and return its child protocol interface, the child is destroyed when dropped.
+    pub fn create_child(&self) -> Result<ServiceBindingChild<'_, 'a, P, B>, efi::Status> {
+        let handle = self.create_child_handle(None)?;
+        match self.boot_services.handle_protocol(handle, &P::CHILD) {
+            Ok(interface) => {
+                Ok(ServiceBindingChild { service_binding: self, handle, interface: NonNull::from(interface) })
+            }
+            Err(status) => {
+                let _ = self.destroy_child_handle(handle);
+                Err(status)
+            }
+        }
+    }
+}
+
+impl<P: ServiceBindingProtocol, B: BootServices> fmt::Debug for ServiceBinding<'_, P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceBinding").field("handle", &self.handle).field("interface", &self.interface).finish()
+    }
+}
+
+/// Child created by a [`ServiceBinding`], dereferencing to its child protocol interface.
+///
+/// Mutable access is only given through [`ServiceBindingChild::interface_mut`] because other agents may have the same
+/// interface opened on the child handle.
+#[must_use]
+pub struct ServiceBindingChild<'s, 'a, P: ServiceBindingProtocol, B: BootServices> {
+    service_binding: &'s ServiceBinding<'a, P, B>,
+    handle: efi::Handle,
+    interface: NonNull<<P::Child as Protocol>::Interface>,
+}
+
+impl<P: ServiceBindingProtocol, B: BootServices> ServiceBindingChild<'_, '_, P, B> {
+    /// Child handle on which the child protocol is installed.
+    pub fn handle(&self) -> efi::Handle {
+        self.handle
+    }
+
+    /// Returns a mutable reference to the child protocol interface.
+    ///
+    /// # Safety
+    ///
+    /// When calling this method, you have to make sure that no other reference to the interface is alive, including
+    /// one obtained by another agent through `HandleProtocol()` or `OpenProtocol()` on the child handle.
+    pub unsafe fn interface_mut(&mut self) -> &mut <P::Child as Protocol>::Interface {
+        self.interface.as_mut()
+    }
+
+    /// Destroy the child, returning the status of `DestroyChild()`.
+    pub fn destroy(self) -> Result<(), efi::Status> {
+        let result = self.service_binding.destroy_child_handle(self.handle);
+        core::mem::forget(self);
+        result
+    }
+}
+
+impl<P: ServiceBindingProtocol, B: BootServices> Deref for ServiceBindingChild<'_, '_, P, B> {
+    type Target = <P::Child as Protocol>::Interface;
+
+    fn deref(&self) -> &Self::Target {
+        //SAFETY: The interface is valid as long as the child handle is not destroyed.
+        unsafe { self.interface.as_ref() }
+    }
+}
+
+impl<P: ServiceBindingProtocol, B: BootServices> Drop for ServiceBindingChild<'_, '_, P, B> {
+    fn drop(&mut self) {
+        let _ = self.service_binding.destroy_child_handle(self.handle);
+    }
+}
+
+impl<P: ServiceBindingProtocol, B: BootServices> fmt::Debug for ServiceBindingChild<'_, '_, P, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceBindingChild").field("handle", &self.handle).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MockBootServices;
+    use alloc::boxed::Box;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    crate::define_protocol!(struct TestChild(u32) = "6C1B8C51-3C5E-4A40-8C9B-5B4B2E34C0A1");
+    crate::define_protocol!(
+        struct TestServiceBinding(efi::protocols::service_binding::Protocol) = "6C1B8C51-3C5E-4A40-8C9B-5B4B2E34C0A2"
+    );
+
+    impl ServiceBindingProtocol for TestServiceBinding {
+        type Child = TestChild;
+        const CHILD: Self::Child = TestChild;
+    }
+
+    static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "efiapi" fn create_child(
+        _this: *mut efi::protocols::service_binding::Protocol,
+        handle: *mut efi::Handle,
+    ) -> efi::Status {
+        assert!(unsafe { *handle }.is_null());
+        unsafe { *handle = 0x10 as efi::Handle };
+        efi::Status::SUCCESS
+    }
+
+    extern "efiapi" fn destroy_child(
+        _this: *mut efi::protocols::service_binding::Protocol,
+        handle: efi::Handle,
+    ) -> efi::Status {
+        assert_eq!(0x10, handle as usize);
+        DESTROYED.fetch_add(1, Ordering::SeqCst);
+        efi::Status::SUCCESS
+    }
+
+    #[test]
+    fn test_service_binding_child() {
+        let mut boot_services = MockBootServices::new();
+        boot_services
+            .expect_handle_protocol::<TestServiceBinding, efi::protocols::service_binding::Protocol>()
+            .returning(|handle, _| {
+                assert_eq!(1, handle as usize);
+                Ok(Box::leak(Box::new(efi::protocols::service_binding::Protocol { create_child, destroy_child })))
+            });
+        boot_services.expect_handle_protocol::<TestChild, u32>().returning(|handle, _| {
+            assert_eq!(0x10, handle as usize);
+            Ok(Box::leak(Box::new(42)))
+        });
+
+        let service_binding = ServiceBinding::new(&boot_services, 1 as efi::Handle, &TestServiceBinding).unwrap();
+        assert_eq!(1, service_binding.handle() as usize);
+
+        let mut child = service_binding.create_child().unwrap();
+        assert_eq!(0x10, child.handle() as usize);
+        assert_eq!(42, *child);
+        unsafe { *child.interface_mut() += 1 };
+        assert_eq!(43, *child);
+        drop(child);
+        assert_eq!(1, DESTROYED.load(Ordering::SeqCst));
+
+        assert_eq!(Ok(()), service_binding.create_child().unwrap().destroy());
+        assert_eq!(2, DESTROYED.load(Ordering::SeqCst));
+    }
+}
@@ -1,5 +1,5 @@
 use core::{
-    mem::ManuallyDrop,
+    mem::{self, ManuallyDrop},
     ops::{Deref, DerefMut},
     pin::Pin,
     ptr,
@@ -9,12 +9,25 @@ use alloc::boxed::Box;
 
 /// <div class="warning">
 ///
-/// This should be implemented **only** on type that have the same memory layout as `*mut T` and that can be recreated with [`core::mem::transmute`].
+/// This should be implemented **only** on type that have the same memory layout as `*mut T` and that can be recreated with [`core::mem::transmute`],
+/// unless [`StaticPtr::drop_raw`] is implemented.
 ///
 /// </div>
 pub unsafe trait StaticPtr {
     type Pointee: Sized + 'static;
     fn into_raw(self) -> *const Self::Pointee;
+
+    /// Release a pointer returned by [`StaticPtr::into_raw`], by default the pointer is transmuted back to `Self` and dropped.
+    ///
+    /// # Safety
+    ///
+    /// *ptr* must come from [`StaticPtr::into_raw`] and must not be used afterward.
+    unsafe fn drop_raw(ptr: *const Self::Pointee)
+    where
+        Self: Sized,
+    {
+        drop(mem::transmute_copy::<*const Self::Pointee, Self>(&ptr));
+    }
 }
 
 /// <div class="warning">
@@ -91,6 +104,12 @@ where
     fn into_raw(self) -> *const Self::Pointee {
         Option::map_or(self, ptr::null(), |t| T::into_raw(t))
     }
+
+    unsafe fn drop_raw(ptr: *const Self::Pointee) {
+        if !ptr.is_null() {
+            T::drop_raw(ptr)
+        }
+    }
 }
 
 unsafe impl<T> StaticPtrMut for Option<T>
@@ -113,6 +132,8 @@ where
     fn into_raw(self) -> *const Self::Pointee {
         ManuallyDrop::into_inner(self).into_raw()
     }
+
+    unsafe fn drop_raw(_ptr: *const Self::Pointee) {}
 }
 
 unsafe impl<T> StaticPtrMut for ManuallyDrop<T>
@@ -136,6 +157,10 @@ where
     fn into_raw(self) -> *const Self::Pointee {
         Pin::into_inner(self).into_raw()
     }
+
+    unsafe fn drop_raw(ptr: *const Self::Pointee) {
+        T::drop_raw(ptr)
+    }
 }
 
 unsafe impl<T> StaticPtrMut for Pin<T>
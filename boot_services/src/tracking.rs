@@ -0,0 +1,644 @@
+//! [`BootServices`] decorator tracking the memory allocations, to find leaks and invalid frees.
+//!
+//! ```ignore
+//! let boot_services = TrackingBootServices::new(StandardBootServices::new(efi_boot_services));
+//! run_driver_entry_point(&boot_services);
+//! run_driver_unload(&boot_services);
+//! boot_services.assert_no_leaks();
+//! ```
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use core::{cell::RefCell, ffi::c_void, mem, time::Duration};
+
+use r_efi::efi;
+
+use crate::{
+    allocation::{AllocType, MemoryMap, MemoryType, UEFI_PAGE_SIZE},
+    boxed::BootServicesBox,
+    event::{EventNotifyCallback, EventTimerType, EventType},
+    protocol_handler::{HandleSearchType, OpenProtocolAttributes, OpenProtocolInformationEntry, Registration},
+    tpl::Tpl,
+    BootServices,
+};
+
+/// Outstanding memory allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allocation {
+    /// Pool memory allocated with [`BootServices::allocate_pool`] or returned by a boot service.
+    Pool { address: usize, memory_type: MemoryType, size: usize },
+    /// Pages allocated with [`BootServices::allocate_pages`], partially freed pages are split.
+    Pages { address: usize, memory_type: MemoryType, nb_pages: usize },
+}
+
+impl Allocation {
+    /// Start address of the allocation.
+    pub fn address(&self) -> usize {
+        match self {
+            Allocation::Pool { address, .. } | Allocation::Pages { address, .. } => *address,
+        }
+    }
+}
+
+/// Invalid free detected by [`TrackingBootServices`], the free is not forwarded to the inner boot services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackingError {
+    /// The memory at *address* has already been freed.
+    DoubleFree { address: usize },
+    /// The memory at *address* has never been allocated.
+    NeverAllocated { address: usize },
+}
+
+#[derive(Default)]
+struct TrackingState {
+    pools: BTreeMap<usize, (MemoryType, usize)>,
+    pages: BTreeMap<usize, (MemoryType, usize)>,
+    freed_pools: BTreeSet<usize>,
+    freed_pages: BTreeMap<usize, usize>,
+    errors: Vec<TrackingError>,
+}
+
+impl TrackingState {
+    fn record_pool(&mut self, address: usize, memory_type: MemoryType, size: usize) {
+        self.freed_pools.remove(&address);
+        self.pools.insert(address, (memory_type, size));
+    }
+
+    fn record_pages(&mut self, address: usize, memory_type: MemoryType, nb_pages: usize) {
+        let end = address + nb_pages * UEFI_PAGE_SIZE;
+        self.freed_pages
+            .retain(|freed, freed_pages| *freed + *freed_pages * UEFI_PAGE_SIZE <= address || end <= *freed);
+        self.pages.insert(address, (memory_type, nb_pages));
+    }
+
+    fn check_pool_free(&mut self, address: usize) -> Result<(), efi::Status> {
+        if self.pools.contains_key(&address) {
+            return Ok(());
+        }
+        self.errors.push(if self.freed_pools.contains(&address) {
+            TrackingError::DoubleFree { address }
+        } else {
+            TrackingError::NeverAllocated { address }
+        });
+        Err(efi::Status::INVALID_PARAMETER)
+    }
+
+    fn record_pool_free(&mut self, address: usize) {
+        self.pools.remove(&address);
+        self.freed_pools.insert(address);
+    }
+
+    /// Return the base address of the pages allocation containing the pages to free.
+    fn check_pages_free(&mut self, address: usize, nb_pages: usize) -> Result<usize, efi::Status> {
+        let end = address + nb_pages * UEFI_PAGE_SIZE;
+        let contains = |(start, count): (&usize, &usize)| *start <= address && end <= start + count * UEFI_PAGE_SIZE;
+
+        if let Some((start, _)) =
+            self.pages.range(..=address).next_back().filter(|(start, (_, count))| contains((start, count)))
+        {
+            return Ok(*start);
+        }
+        self.errors.push(if self.freed_pages.range(..=address).next_back().is_some_and(contains) {
+            TrackingError::DoubleFree { address }
+        } else {
+            TrackingError::NeverAllocated { address }
+        });
+        Err(efi::Status::INVALID_PARAMETER)
+    }
+
+    fn record_pages_free(&mut self, start: usize, address: usize, nb_pages: usize) {
+        let Some((memory_type, count)) = self.pages.remove(&start) else {
+            return;
+        };
+        let head_pages = (address - start) / UEFI_PAGE_SIZE;
+        let tail_pages = count - head_pages - nb_pages;
+        if head_pages > 0 {
+            self.pages.insert(start, (memory_type, head_pages));
+        }
+        if tail_pages > 0 {
+            self.pages.insert(address + nb_pages * UEFI_PAGE_SIZE, (memory_type, tail_pages));
+        }
+        self.freed_pages.insert(address, nb_pages);
+    }
+}
+
+/// Boot services forwarding to an inner implementation while recording the allocated pool and pages, and the
+/// matching frees.
+///
+/// Buffers allocated by the firmware and returned by the boot services, like [`BootServices::locate_handle_buffer`],
+/// are tracked too since they must be freed by the caller.
+pub struct TrackingBootServices<B: BootServices> {
+    inner: B,
+    state: RefCell<TrackingState>,
+}
+
+impl<B: BootServices> TrackingBootServices<B> {
+    /// Wrap *inner*, with no allocation recorded yet.
+    pub fn new(inner: B) -> Self {
+        Self { inner, state: RefCell::new(TrackingState::default()) }
+    }
+
+    /// Boot services the calls are forwarded to, allocations made directly through them are not tracked.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Stop tracking and return the inner boot services.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Snapshot of the outstanding allocations, sorted by address.
+    pub fn outstanding_allocations(&self) -> Vec<Allocation> {
+        self.with_state(|state| {
+            let pools = state.pools.iter().map(|(address, (memory_type, size))| Allocation::Pool {
+                address: *address,
+                memory_type: *memory_type,
+                size: *size,
+            });
+            let pages = state.pages.iter().map(|(address, (memory_type, nb_pages))| Allocation::Pages {
+                address: *address,
+                memory_type: *memory_type,
+                nb_pages: *nb_pages,
+            });
+            let mut allocations = pools.chain(pages).collect::<Vec<_>>();
+            allocations.sort_by_key(Allocation::address);
+            allocations
+        })
+    }
+
+    /// Invalid frees detected so far.
+    pub fn errors(&self) -> Vec<TrackingError> {
+        self.with_state(|state| state.errors.clone())
+    }
+
+    /// Forget the recorded allocations and errors, e.g. to ignore the allocations made before the code under test.
+    pub fn clear(&self) {
+        self.with_state(|state| *state = TrackingState::default());
+    }
+
+    /// # Panics
+    ///
+    /// Panics if some allocations are outstanding or if an invalid free has been detected.
+    #[track_caller]
+    pub fn assert_no_leaks(&self) {
+        let errors = self.errors();
+        assert!(errors.is_empty(), "Invalid free detected: {errors:x?}");
+        let allocations = self.outstanding_allocations();
+        assert!(allocations.is_empty(), "{} allocation(s) leaked: {allocations:x?}", allocations.len());
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut TrackingState) -> R) -> R {
+        // Allocation services can be called from event notifications up to TPL_NOTIFY.
+        let old_tpl = self.inner.raise_tpl(Tpl::NOTIFY);
+        let result = f(&mut self.state.borrow_mut());
+        self.inner.restore_tpl(old_tpl);
+        result
+    }
+
+    /// Take ownership of a slice returned by the inner boot services and track it.
+    fn adopt<'a, T>(&'a self, boxed: BootServicesBox<'_, [T], B>) -> BootServicesBox<'a, [T], Self> {
+        let memory_type = boxed.memory_type();
+        let ptr = boxed.into_raw();
+        //SAFETY: The pointer comes from a box that has just been released.
+        let size = mem::size_of_val(unsafe { &*ptr });
+        if size > 0 {
+            self.with_state(|state| state.record_pool(ptr as *mut T as usize, memory_type, size));
+        }
+        //SAFETY: The pointer comes from a box that has just been released.
+        unsafe { BootServicesBox::from_raw_pool(ptr, self) }
+    }
+}
+
+impl<B: BootServices> BootServices for TrackingBootServices<B> {
+    unsafe fn create_event_unchecked<T: Sized + 'static>(
+        &self,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: Option<EventNotifyCallback<*mut T>>,
+        notify_context: *mut T,
+    ) -> Result<efi::Event, efi::Status> {
+        self.inner.create_event_unchecked(event_type, notify_tpl, notify_function, notify_context)
+    }
+
+    unsafe fn create_event_ex_unchecked<T: Sized + 'static>(
+        &self,
+        event_type: EventType,
+        notify_tpl: Tpl,
+        notify_function: EventNotifyCallback<*mut T>,
+        notify_context: *mut T,
+        event_group: &'static efi::Guid,
+    ) -> Result<efi::Event, efi::Status> {
+        self.inner.create_event_ex_unchecked(event_type, notify_tpl, notify_function, notify_context, event_group)
+    }
+
+    fn close_event(&self, event: efi::Event) -> Result<(), efi::Status> {
+        self.inner.close_event(event)
+    }
+
+    fn signal_event(&self, event: efi::Event) -> Result<(), efi::Status> {
+        self.inner.signal_event(event)
+    }
+
+    fn wait_for_event(&self, events: &mut [efi::Event]) -> Result<usize, efi::Status> {
+        self.inner.wait_for_event(events)
+    }
+
+    fn check_event(&self, event: efi::Event) -> Result<(), efi::Status> {
+        self.inner.check_event(event)
+    }
+
+    fn set_timer(&self, event: efi::Event, timer_type: EventTimerType, trigger_time: u64) -> Result<(), efi::Status> {
+        self.inner.set_timer(event, timer_type, trigger_time)
+    }
+
+    fn raise_tpl(&self, tpl: Tpl) -> Tpl {
+        self.inner.raise_tpl(tpl)
+    }
+
+    fn restore_tpl(&self, tpl: Tpl) {
+        self.inner.restore_tpl(tpl)
+    }
+
+    fn allocate_pages(
+        &self,
+        alloc_type: AllocType,
+        memory_type: MemoryType,
+        nb_pages: usize,
+    ) -> Result<usize, efi::Status> {
+        let address = self.inner.allocate_pages(alloc_type, memory_type, nb_pages)?;
+        self.with_state(|state| state.record_pages(address, memory_type, nb_pages));
+        Ok(address)
+    }
+
+    fn free_pages(&self, address: usize, nb_pages: usize) -> Result<(), efi::Status> {
+        let start = self.with_state(|state| state.check_pages_free(address, nb_pages))?;
+        self.inner.free_pages(address, nb_pages)?;
+        self.with_state(|state| state.record_pages_free(start, address, nb_pages));
+        Ok(())
+    }
+
+    fn get_memory_map(&self) -> Result<MemoryMap<Self>, (efi::Status, usize)> {
+        let (buffer, map_size, map_key, descriptor_size, descriptor_version) =
+            self.inner.get_memory_map()?.into_raw_parts();
+        Ok(MemoryMap::new(self.adopt(buffer), map_size, map_key, descriptor_size, descriptor_version))
+    }
+
+    fn allocate_pool(&self, pool_type: MemoryType, size: usize) -> Result<*mut u8, efi::Status> {
+        let buffer = self.inner.allocate_pool(pool_type, size)?;
+        self.with_state(|state| state.record_pool(buffer as usize, pool_type, size));
+        Ok(buffer)
+    }
+
+    fn free_pool(&self, buffer: *mut u8) -> Result<(), efi::Status> {
+        self.with_state(|state| state.check_pool_free(buffer as usize))?;
+        self.inner.free_pool(buffer)?;
+        self.with_state(|state| state.record_pool_free(buffer as usize));
+        Ok(())
+    }
+
+    unsafe fn install_protocol_interface_unchecked(
+        &self,
+        handle: Option<efi::Handle>,
+        protocol: &'static efi::Guid,
+        interface: *mut c_void,
+    ) -> Result<efi::Handle, efi::Status> {
+        self.inner.install_protocol_interface_unchecked(handle, protocol, interface)
+    }
+
+    unsafe fn uninstall_protocol_interface_unchecked(
+        &self,
+        handle: efi::Handle,
+        protocol: &'static efi::Guid,
+        interface: *mut c_void,
+    ) -> Result<(), efi::Status> {
+        self.inner.uninstall_protocol_interface_unchecked(handle, protocol, interface)
+    }
+
+    unsafe fn reinstall_protocol_interface_unchecked(
+        &self,
+        handle: efi::Handle,
+        protocol: &'static efi::Guid,
+        old_protocol_interface: *mut c_void,
+        new_protocol_interface: *mut c_void,
+    ) -> Result<(), efi::Status> {
+        self.inner.reinstall_protocol_interface_unchecked(
+            handle,
+            protocol,
+            old_protocol_interface,
+            new_protocol_interface,
+        )
+    }
+
+    fn register_protocol_notify(
+        &self,
+        protocol: &'static efi::Guid,
+        event: efi::Event,
+    ) -> Result<Registration, efi::Status> {
+        self.inner.register_protocol_notify(protocol, event)
+    }
+
+    fn locate_handle(
+        &self,
+        search_type: HandleSearchType,
+    ) -> Result<BootServicesBox<[efi::Handle], Self>, efi::Status> {
+        Ok(self.adopt(self.inner.locate_handle(search_type)?))
+    }
+
+    unsafe fn handle_protocol_unchecked(
+        &self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+    ) -> Result<*mut c_void, efi::Status> {
+        self.inner.handle_protocol_unchecked(handle, protocol)
+    }
+
+    unsafe fn locate_device_path(
+        &self,
+        protocol: &efi::Guid,
+        device_path: *mut *mut efi::protocols::device_path::Protocol,
+    ) -> Result<efi::Handle, efi::Status> {
+        self.inner.locate_device_path(protocol, device_path)
+    }
+
+    unsafe fn open_protocol_unchecked(
+        &self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+        agent_handle: efi::Handle,
+        controller_handle: efi::Handle,
+        attribute: OpenProtocolAttributes,
+    ) -> Result<*mut c_void, efi::Status> {
+        self.inner.open_protocol_unchecked(handle, protocol, agent_handle, controller_handle, attribute)
+    }
+
+    fn close_protocol(
+        &self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+        agent_handle: efi::Handle,
+        controller_handle: efi::Handle,
+    ) -> Result<(), efi::Status> {
+        self.inner.close_protocol(handle, protocol, agent_handle, controller_handle)
+    }
+
+    fn open_protocol_information(
+        &self,
+        handle: efi::Handle,
+        protocol: &efi::Guid,
+    ) -> Result<BootServicesBox<[OpenProtocolInformationEntry], Self>, efi::Status> {
+        Ok(self.adopt(self.inner.open_protocol_information(handle, protocol)?))
+    }
+
+    unsafe fn connect_controller(
+        &self,
+        controller_handle: efi::Handle,
+        driver_image_handle: Vec<efi::Handle>,
+        remaining_device_path: *mut efi::protocols::device_path::Protocol,
+        recursive: bool,
+    ) -> Result<(), efi::Status> {
+        self.inner.connect_controller(controller_handle, driver_image_handle, remaining_device_path, recursive)
+    }
+
+    fn disconnect_controller(
+        &self,
+        controller_handle: efi::Handle,
+        driver_image_handle: Option<efi::Handle>,
+        child_handle: Option<efi::Handle>,
+    ) -> Result<(), efi::Status> {
+        self.inner.disconnect_controller(controller_handle, driver_image_handle, child_handle)
+    }
+
+    fn protocols_per_handle(&self, handle: efi::Handle) -> Result<BootServicesBox<[efi::Guid], Self>, efi::Status> {
+        Ok(self.adopt(self.inner.protocols_per_handle(handle)?))
+    }
+
+    fn locate_handle_buffer(
+        &self,
+        search_type: HandleSearchType,
+    ) -> Result<BootServicesBox<[efi::Handle], Self>, efi::Status> {
+        Ok(self.adopt(self.inner.locate_handle_buffer(search_type)?))
+    }
+
+    unsafe fn locate_protocol_unchecked(
+        &self,
+        protocol: &'static efi::Guid,
+        registration: *mut c_void,
+    ) -> Result<*mut c_void, efi::Status> {
+        self.inner.locate_protocol_unchecked(protocol, registration)
+    }
+
+    unsafe fn load_image_unchecked(
+        &self,
+        boot_policy: bool,
+        parent_image_handle: efi::Handle,
+        device_path: *mut efi::protocols::device_path::Protocol,
+        source_buffer: *mut c_void,
+        source_size: usize,
+    ) -> Result<efi::Handle, efi::Status> {
+        self.inner.load_image_unchecked(boot_policy, parent_image_handle, device_path, source_buffer, source_size)
+    }
+
+    unsafe fn start_image_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> Result<(), efi::Status> {
+        let result = self.inner.start_image_unchecked(image_handle, exit_data_size, exit_data);
+        // The exit data is allocated by the started image and must be freed by the caller.
+        if !exit_data.is_null() && !(*exit_data).is_null() && !exit_data_size.is_null() {
+            self.with_state(|state| {
+                state.record_pool(*exit_data as usize, MemoryType::BOOT_SERVICES_DATA, *exit_data_size)
+            });
+        }
+        result
+    }
+
+    unsafe fn unload_image_unchecked(&self, image_handle: efi::Handle) -> Result<(), efi::Status> {
+        self.inner.unload_image_unchecked(image_handle)
+    }
+
+    unsafe fn exit_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        exit_status: efi::Status,
+        exit_data_size: usize,
+        exit_data: *mut u16,
+    ) -> Result<(), efi::Status> {
+        self.inner.exit_unchecked(image_handle, exit_status, exit_data_size, exit_data)
+    }
+
+    unsafe fn exit_boot_services_unchecked(
+        &self,
+        image_handle: efi::Handle,
+        map_key: usize,
+    ) -> Result<(), efi::Status> {
+        self.inner.exit_boot_services_unchecked(image_handle, map_key)
+    }
+
+    fn set_watchdog_timer(
+        &self,
+        timeout: usize,
+        watchdog_code: u64,
+        watchdog_data: Option<&[u16]>,
+    ) -> Result<(), efi::Status> {
+        self.inner.set_watchdog_timer(timeout, watchdog_code, watchdog_data)
+    }
+
+    fn stall(&self, duration: Duration) -> Result<(), efi::Status> {
+        self.inner.stall(duration)
+    }
+
+    unsafe fn copy_mem_unchecked(&self, destination: *mut c_void, source: *const c_void, length: usize) {
+        self.inner.copy_mem_unchecked(destination, source, length)
+    }
+
+    unsafe fn set_mem_unchecked(&self, buffer: *mut c_void, size: usize, value: u8) {
+        self.inner.set_mem_unchecked(buffer, size, value)
+    }
+
+    fn get_next_monotonic_count(&self) -> Result<u64, efi::Status> {
+        self.inner.get_next_monotonic_count()
+    }
+
+    unsafe fn install_configuration_table_unchecked(
+        &self,
+        guid: &efi::Guid,
+        table: *mut c_void,
+    ) -> Result<(), efi::Status> {
+        self.inner.install_configuration_table_unchecked(guid, table)
+    }
+
+    fn calculate_crc32(&self, data: &[u8]) -> Result<u32, efi::Status> {
+        self.inner.calculate_crc32(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{boxed::test::free_pool_boot_services, pages::Pages, MockBootServices};
+    use mockall::predicate::eq;
+
+    fn boot_services() -> MockBootServices {
+        let mut boot_services = MockBootServices::new();
+        boot_services.expect_raise_tpl().with(eq(Tpl::NOTIFY)).return_const(Tpl::APPLICATION);
+        boot_services.expect_restore_tpl().with(eq(Tpl::APPLICATION)).return_const(());
+        boot_services
+    }
+
+    #[test]
+    fn test_pool_allocations_are_tracked() {
+        let mut inner = boot_services();
+        inner.expect_allocate_pool().returning(|_, size| Ok((0x1000 + size) as *mut u8));
+        inner.expect_free_pool().times(2).returning(|_| Ok(()));
+        let boot_services = TrackingBootServices::new(inner);
+
+        let first = boot_services.allocate_pool(MemoryType::LOADER_DATA, 0x10).unwrap();
+        let second = boot_services.allocate_pool(MemoryType::BOOT_SERVICES_DATA, 0x20).unwrap();
+        assert_eq!(
+            vec![
+                Allocation::Pool { address: 0x1010, memory_type: MemoryType::LOADER_DATA, size: 0x10 },
+                Allocation::Pool { address: 0x1020, memory_type: MemoryType::BOOT_SERVICES_DATA, size: 0x20 },
+            ],
+            boot_services.outstanding_allocations()
+        );
+
+        boot_services.free_pool(second).unwrap();
+        boot_services.free_pool(first).unwrap();
+        boot_services.assert_no_leaks();
+    }
+
+    #[test]
+    #[should_panic = "1 allocation(s) leaked: [Pool { address: 1010, memory_type: MemoryType(LoaderData), size: 10 }]"]
+    fn test_assert_no_leaks_with_leak() {
+        let mut inner = boot_services();
+        inner.expect_allocate_pool().returning(|_, size| Ok((0x1000 + size) as *mut u8));
+        let boot_services = TrackingBootServices::new(inner);
+
+        boot_services.allocate_pool(MemoryType::LOADER_DATA, 0x10).unwrap();
+        boot_services.assert_no_leaks();
+    }
+
+    #[test]
+    fn test_invalid_frees_are_not_forwarded() {
+        let mut inner = boot_services();
+        inner.expect_allocate_pool().returning(|_, _| Ok(0x1000 as *mut u8));
+        inner.expect_free_pool().times(1).returning(|_| Ok(()));
+        inner.expect_free_pages().never();
+        let boot_services = TrackingBootServices::new(inner);
+
+        let buffer = boot_services.allocate_pool(MemoryType::LOADER_DATA, 0x10).unwrap();
+        assert_eq!(Ok(()), boot_services.free_pool(buffer));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), boot_services.free_pool(buffer));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), boot_services.free_pool(0x2000 as *mut u8));
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), boot_services.free_pages(0x3000, 1));
+        assert_eq!(
+            vec![
+                TrackingError::DoubleFree { address: 0x1000 },
+                TrackingError::NeverAllocated { address: 0x2000 },
+                TrackingError::NeverAllocated { address: 0x3000 },
+            ],
+            boot_services.errors()
+        );
+        assert!(boot_services.outstanding_allocations().is_empty());
+
+        boot_services.clear();
+        boot_services.assert_no_leaks();
+    }
+
+    #[test]
+    fn test_partially_freed_pages() {
+        let mut inner = boot_services();
+        inner.expect_allocate_pages().returning(|_, _, _| Ok(0x1FD000));
+        inner.expect_free_pages().returning(|_, _| Ok(()));
+        let boot_services = TrackingBootServices::new(inner);
+
+        // The pages around the aligned range are freed right away.
+        let pages = Pages::allocate_aligned(&boot_services, MemoryType::RUNTIME_SERVICES_DATA, 2, 0x200000).unwrap();
+        assert_eq!(
+            vec![Allocation::Pages { address: 0x200000, memory_type: MemoryType::RUNTIME_SERVICES_DATA, nb_pages: 2 }],
+            boot_services.outstanding_allocations()
+        );
+        drop(pages);
+        boot_services.assert_no_leaks();
+
+        assert_eq!(Err(efi::Status::INVALID_PARAMETER), boot_services.free_pages(0x201000, 1));
+        assert_eq!(vec![TrackingError::DoubleFree { address: 0x201000 }], boot_services.errors());
+    }
+
+    #[test]
+    fn test_firmware_buffers_are_tracked() {
+        static HANDLES: [usize; 2] = [1, 2];
+
+        // Boot services of the box returned by the inner boot services, not used since the box is adopted.
+        let box_boot_services = free_pool_boot_services();
+
+        let mut inner = boot_services();
+        inner.expect_locate_handle_buffer().returning(move |_| {
+            Ok(unsafe { BootServicesBox::from_raw_parts(HANDLES.as_ptr() as *mut efi::Handle, 2, box_boot_services) })
+        });
+        inner
+            .expect_free_pool()
+            .withf(|buffer| *buffer as usize == HANDLES.as_ptr() as usize)
+            .times(1)
+            .returning(|_| Ok(()));
+        let boot_services = TrackingBootServices::new(inner);
+
+        let handles = boot_services.locate_handle_buffer(HandleSearchType::AllHandle).unwrap();
+        assert_eq!(2, handles.len());
+        assert_eq!(
+            vec![Allocation::Pool {
+                address: HANDLES.as_ptr() as usize,
+                memory_type: MemoryType::BOOT_SERVICES_DATA,
+                size: 2 * mem::size_of::<efi::Handle>()
+            }],
+            boot_services.outstanding_allocations()
+        );
+        drop(handles);
+        boot_services.assert_no_leaks();
+    }
+}
@@ -1,14 +1,9 @@
-use core::{
-    ffi::c_void,
-    mem::MaybeUninit,
-    ptr::{self, NonNull},
-};
+use core::{ffi::c_void, ptr::NonNull};
 
 use boot_services::{
-    event::EventType,
-    protocol_handler::{DriverBinding, Protocol, Registration},
+    protocol_handler::{DriverBinding, ProtocolNotify},
     tpl::Tpl,
-    BootServices, MockBootServices,
+    MockBootServices,
 };
 use r_efi::efi;
 
@@ -16,27 +11,24 @@ fn main() {
     let mut boot_services = MockBootServices::new();
 
     let _ = boot_services
-        .expect_create_event::<Box<MaybeUninit<Registration>>>()
+        .expect_create_event_unchecked::<c_void>()
         .withf(|_, _, _, _| true)
-        .returning(|_, _, _, _| Ok(ptr::null_mut()));
+        .returning(|_, _, _, _| Ok(NonNull::<c_void>::dangling().as_ptr()));
 
     let _ = boot_services
         .expect_register_protocol_notify()
         .withf(|_, _| true)
         .returning(|_, _| Ok(NonNull::<c_void>::dangling()));
 
-    extern "efiapi" fn event_notify_callback(_event: efi::Event, context: Box<MaybeUninit<Registration>>) {
-        println!("{context:?}")
-    }
-
-    let mut registration = Box::new(MaybeUninit::uninit());
-    let registration_ptr = registration.as_mut_ptr();
+    let _ = boot_services.expect_raise_tpl().return_const(Tpl::APPLICATION);
+    let _ = boot_services.expect_restore_tpl().return_const(());
+    let _ = boot_services.expect_close_event().returning(|_| Ok(()));
 
-    let event = boot_services
-        .create_event(EventType::NOTIFY_SIGNAL, Tpl::CALLBACK, Some(event_notify_callback), registration)
+    let protocol_notify =
+        ProtocolNotify::new(&boot_services, &DriverBinding, Tpl::CALLBACK, false, |handle: efi::Handle, interface| {
+            println!("Driver binding installed on {handle:?}, version {}", interface.version)
+        })
         .unwrap();
 
-    unsafe {
-        registration_ptr.write(boot_services.register_protocol_notify(DriverBinding.protocol_guid(), event).unwrap())
-    }
+    println!("{protocol_notify:?}")
 }